@@ -0,0 +1,310 @@
+//! The [`Tables`] as Arrow record batches, and as Parquet files.
+//! Only built with the `parquet` feature.
+//!
+//! Ticks are `u32`, coordinates and angles `f32`, and repetitive strings like
+//! names and weapons are dictionary encoded. Steam ids are SteamID64s, and
+//! null for bots.
+
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, BooleanArray, DictionaryArray, Float32Array, Int32Array, RecordBatch, UInt32Array,
+    UInt64Array,
+};
+use arrow::datatypes::Int32Type;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::error::Result;
+use crate::steam_id::SteamId;
+use crate::tables::Tables;
+
+/// Collects the columns of one record batch.
+struct Columns<'a, T> {
+    rows: &'a [T],
+    columns: Vec<(&'static str, ArrayRef, bool)>,
+}
+
+impl<'a, T> Columns<'a, T> {
+    fn new(rows: &'a [T]) -> Self {
+        Columns {
+            rows,
+            columns: vec![],
+        }
+    }
+
+    /// Nullability goes by the type of the column, not its data,
+    /// so every demo gets the same schema.
+    fn push(mut self, name: &'static str, array: ArrayRef, nullable: bool) -> Self {
+        self.columns.push((name, array, nullable));
+        self
+    }
+
+    fn u32(self, name: &'static str, f: impl Fn(&T) -> u32) -> Self {
+        let array: UInt32Array = self.rows.iter().map(f).collect::<Vec<_>>().into();
+        self.push(name, Arc::new(array), false)
+    }
+
+    fn opt_u32(self, name: &'static str, f: impl Fn(&T) -> Option<u32>) -> Self {
+        let array: UInt32Array = self.rows.iter().map(f).collect();
+        self.push(name, Arc::new(array), true)
+    }
+
+    fn i32(self, name: &'static str, f: impl Fn(&T) -> i32) -> Self {
+        let array: Int32Array = self.rows.iter().map(f).collect::<Vec<_>>().into();
+        self.push(name, Arc::new(array), false)
+    }
+
+    fn opt_i32(self, name: &'static str, f: impl Fn(&T) -> Option<i32>) -> Self {
+        let array: Int32Array = self.rows.iter().map(f).collect();
+        self.push(name, Arc::new(array), true)
+    }
+
+    fn f32(self, name: &'static str, f: impl Fn(&T) -> f32) -> Self {
+        let array: Float32Array = self.rows.iter().map(f).collect::<Vec<_>>().into();
+        self.push(name, Arc::new(array), false)
+    }
+
+    fn opt_f32(self, name: &'static str, f: impl Fn(&T) -> Option<f32>) -> Self {
+        let array: Float32Array = self.rows.iter().map(f).collect();
+        self.push(name, Arc::new(array), true)
+    }
+
+    fn bool(self, name: &'static str, f: impl Fn(&T) -> bool) -> Self {
+        let array: BooleanArray = self.rows.iter().map(f).collect::<Vec<_>>().into();
+        self.push(name, Arc::new(array), false)
+    }
+
+    fn str(self, name: &'static str, f: impl Fn(&T) -> &str) -> Self {
+        let array: DictionaryArray<Int32Type> = self.rows.iter().map(f).collect();
+        self.push(name, Arc::new(array), false)
+    }
+
+    fn opt_str(self, name: &'static str, f: impl Fn(&T) -> Option<&str>) -> Self {
+        let array: DictionaryArray<Int32Type> = self.rows.iter().map(f).collect();
+        self.push(name, Arc::new(array), true)
+    }
+
+    fn steam_id(self, name: &'static str, f: impl Fn(&T) -> Option<SteamId>) -> Self {
+        let array: UInt64Array = self
+            .rows
+            .iter()
+            .map(|row| f(row).and_then(|id| id.steam_id64()))
+            .collect();
+        self.push(name, Arc::new(array), true)
+    }
+
+    fn finish(self) -> Result<RecordBatch> {
+        Ok(RecordBatch::try_from_iter_with_nullable(self.columns)?)
+    }
+}
+
+impl Tables {
+    /// Every table as a record batch, with the name of the table.
+    pub fn record_batches(&self) -> Result<Vec<(&'static str, RecordBatch)>> {
+        let kills = Columns::new(&self.kills)
+            .u32("tick", |r| r.tick)
+            .u32("round", |r| r.round)
+            .opt_i32("victim_user_id", |r| r.victim_user_id)
+            .str("victim_name", |r| &r.victim_name)
+            .steam_id("victim_steam_id", |r| r.victim_steam_id)
+            .opt_i32("victim_team", |r| r.victim_team)
+            .opt_i32("attacker_user_id", |r| r.attacker_user_id)
+            .str("attacker_name", |r| &r.attacker_name)
+            .steam_id("attacker_steam_id", |r| r.attacker_steam_id)
+            .opt_i32("attacker_team", |r| r.attacker_team)
+            .opt_i32("assister_user_id", |r| r.assister_user_id)
+            .str("assister_name", |r| &r.assister_name)
+            .steam_id("assister_steam_id", |r| r.assister_steam_id)
+            .str("weapon", |r| &r.weapon)
+            .bool("headshot", |r| r.headshot)
+            .i32("penetrated", |r| r.penetrated)
+            .bool("noscope", |r| r.noscope)
+            .bool("thrusmoke", |r| r.thrusmoke)
+            .bool("attackerblind", |r| r.attackerblind)
+            .bool("assistedflash", |r| r.assistedflash)
+            .f32("distance", |r| r.distance)
+            .opt_f32("victim_x", |r| r.victim_x)
+            .opt_f32("victim_y", |r| r.victim_y)
+            .opt_f32("victim_z", |r| r.victim_z)
+            .opt_f32("victim_pitch", |r| r.victim_pitch)
+            .opt_f32("victim_yaw", |r| r.victim_yaw)
+            .opt_f32("attacker_x", |r| r.attacker_x)
+            .opt_f32("attacker_y", |r| r.attacker_y)
+            .opt_f32("attacker_z", |r| r.attacker_z)
+            .opt_f32("attacker_pitch", |r| r.attacker_pitch)
+            .opt_f32("attacker_yaw", |r| r.attacker_yaw)
+            .finish()?;
+        let damage = Columns::new(&self.damage)
+            .u32("tick", |r| r.tick)
+            .u32("round", |r| r.round)
+            .opt_i32("victim_user_id", |r| r.victim_user_id)
+            .str("victim_name", |r| &r.victim_name)
+            .steam_id("victim_steam_id", |r| r.victim_steam_id)
+            .opt_i32("attacker_user_id", |r| r.attacker_user_id)
+            .str("attacker_name", |r| &r.attacker_name)
+            .steam_id("attacker_steam_id", |r| r.attacker_steam_id)
+            .str("weapon", |r| &r.weapon)
+            .i32("dmg_health", |r| r.dmg_health)
+            .i32("dmg_armor", |r| r.dmg_armor)
+            .i32("health", |r| r.health)
+            .i32("armor", |r| r.armor)
+            .i32("hitgroup", |r| r.hitgroup)
+            .finish()?;
+        let rounds = Columns::new(&self.rounds)
+            .u32("number", |r| r.number)
+            .opt_u32("start_tick", |r| r.start_tick)
+            .opt_u32("freeze_end_tick", |r| r.freeze_end_tick)
+            .opt_u32("end_tick", |r| r.end_tick)
+            .opt_i32("winner", |r| r.winner)
+            .opt_i32("reason", |r| r.reason)
+            .u32("score_ct", |r| r.score_ct)
+            .u32("score_t", |r| r.score_t)
+            .bool("is_warmup", |r| r.is_warmup)
+            .bool("is_knife", |r| r.is_knife)
+            .finish()?;
+        let players = Columns::new(&self.players)
+            .i32("user_id", |r| r.user_id)
+            .steam_id("steam_id", |r| r.steam_id)
+            .str("name", |r| &r.name)
+            .bool("is_bot", |r| r.is_bot)
+            .bool("is_hltv", |r| r.is_hltv)
+            .i32("team", |r| r.team)
+            .u32("connected_tick", |r| r.connected_tick)
+            .opt_u32("disconnected_tick", |r| r.disconnected_tick)
+            .finish()?;
+        let positions = Columns::new(&self.positions)
+            .u32("tick", |r| r.tick)
+            .u32("entity_index", |r| r.entity_index as u32)
+            .opt_i32("user_id", |r| r.user_id)
+            .steam_id("steam_id", |r| r.steam_id)
+            .str("name", |r| &r.name)
+            .f32("x", |r| r.x)
+            .f32("y", |r| r.y)
+            .f32("z", |r| r.z)
+            .f32("pitch", |r| r.pitch)
+            .f32("yaw", |r| r.yaw)
+            .f32("velocity_x", |r| r.velocity_x)
+            .f32("velocity_y", |r| r.velocity_y)
+            .f32("velocity_z", |r| r.velocity_z)
+            .i32("health", |r| r.health)
+            .i32("armor", |r| r.armor)
+            .opt_str("weapon", |r| r.weapon.as_deref())
+            .i32("team", |r| r.team)
+            .bool("is_alive", |r| r.is_alive)
+            .bool("is_ducking", |r| r.is_ducking)
+            .bool("is_on_ground", |r| r.is_on_ground)
+            .finish()?;
+        let grenades = Columns::new(&self.grenades)
+            .u32("tick", |r| r.tick)
+            .u32("round", |r| r.round)
+            .str("grenade", |r| r.grenade)
+            .opt_i32("user_id", |r| r.user_id)
+            .str("name", |r| &r.name)
+            .steam_id("steam_id", |r| r.steam_id)
+            .i32("entity_id", |r| r.entity_id)
+            .f32("x", |r| r.x)
+            .f32("y", |r| r.y)
+            .f32("z", |r| r.z)
+            .finish()?;
+        let purchases = Columns::new(&self.purchases)
+            .u32("tick", |r| r.tick)
+            .u32("round", |r| r.round)
+            .opt_i32("user_id", |r| r.user_id)
+            .str("name", |r| &r.name)
+            .steam_id("steam_id", |r| r.steam_id)
+            .i32("team", |r| r.team)
+            .str("weapon", |r| &r.weapon)
+            .finish()?;
+        let scoreboard = Columns::new(&self.scoreboard)
+            .i32("user_id", |r| r.user_id)
+            .steam_id("steam_id", |r| r.steam_id)
+            .str("name", |r| &r.name)
+            .i32("team", |r| r.team)
+            .u32("rounds", |r| r.rounds)
+            .u32("kills", |r| r.kills)
+            .u32("deaths", |r| r.deaths)
+            .u32("assists", |r| r.assists)
+            .u32("headshot_kills", |r| r.headshot_kills)
+            .f32("headshot_pct", |r| r.headshot_pct)
+            .i32("damage", |r| r.damage)
+            .f32("adr", |r| r.adr)
+            .f32("kast", |r| r.kast)
+            .u32("first_kills", |r| r.first_kills)
+            .u32("first_deaths", |r| r.first_deaths)
+            .u32("rounds_2k", |r| r.rounds_2k)
+            .u32("rounds_3k", |r| r.rounds_3k)
+            .u32("rounds_4k", |r| r.rounds_4k)
+            .u32("rounds_5k", |r| r.rounds_5k)
+            .u32("clutches", |r| r.clutches)
+            .u32("clutches_won", |r| r.clutches_won)
+            .i32("utility_damage", |r| r.utility_damage)
+            .u32("enemies_flashed", |r| r.enemies_flashed)
+            .f32("kpr", |r| r.kpr)
+            .f32("dpr", |r| r.dpr)
+            .f32("apr", |r| r.apr)
+            .f32("impact", |r| r.impact)
+            .f32("rating", |r| r.rating)
+            .finish()?;
+        let economy = Columns::new(&self.economy)
+            .u32("round", |r| r.round)
+            .i32("team", |r| r.team)
+            .u32("players", |r| r.players)
+            .i32("start_money", |r| r.start_money)
+            .i32("round_start_equipment_value", |r| {
+                r.round_start_equipment_value
+            })
+            .i32("money", |r| r.money)
+            .i32("spent", |r| r.spent)
+            .i32("equipment_value", |r| r.equipment_value)
+            .str("buy", |r| r.buy.as_str())
+            .finish()?;
+        let player_economy = Columns::new(&self.player_economy)
+            .u32("round", |r| r.round)
+            .opt_i32("user_id", |r| r.user_id)
+            .steam_id("steam_id", |r| r.steam_id)
+            .str("name", |r| &r.name)
+            .i32("team", |r| r.team)
+            .i32("start_money", |r| r.start_money)
+            .i32("round_start_equipment_value", |r| {
+                r.round_start_equipment_value
+            })
+            .i32("money", |r| r.money)
+            .i32("spent", |r| r.spent)
+            .i32("equipment_value", |r| r.equipment_value)
+            .str("inventory", |r| &r.inventory)
+            .str("purchases", |r| &r.purchases)
+            .finish()?;
+        Ok(vec![
+            ("kills", kills),
+            ("damage", damage),
+            ("rounds", rounds),
+            ("players", players),
+            ("positions", positions),
+            ("grenades", grenades),
+            ("purchases", purchases),
+            ("scoreboard", scoreboard),
+            ("economy", economy),
+            ("player_economy", player_economy),
+        ])
+    }
+
+    /// Write each table to its own snappy compressed Parquet file in `dir`,
+    /// like `kills.parquet`. The directory is created if it doesn't exist.
+    pub fn write_parquet(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        for (name, batch) in self.record_batches()? {
+            let file = File::create(dir.join(format!("{name}.parquet")))?;
+            let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props.clone()))?;
+            writer.write(&batch)?;
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
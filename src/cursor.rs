@@ -4,7 +4,9 @@
 use std::borrow::Cow;
 use std::cell::Cell;
 
-use bitbuffer::{BitReadBuffer, LittleEndian};
+use bitbuffer::{BitError, BitReadBuffer, LittleEndian};
+
+use crate::error::{DemoError, Result};
 
 #[derive(Debug)]
 pub struct Cursor<'a> {
@@ -12,112 +14,233 @@ pub struct Cursor<'a> {
     buf: BitReadBuffer<'a, LittleEndian>,
     /// The position, IN BITS, of the buffer.
     bit_pos: Cell<usize>,
+    /// Where this buffer starts, IN BYTES, in the file it came from.
+    /// Only used to report useful offsets in errors.
+    base: usize,
 }
 
 /// All methods read little-endian bytes.
 impl<'a> Cursor<'a> {
     pub fn new(buf: &'a [u8]) -> Cursor<'a> {
+        Self::with_offset(buf, 0)
+    }
+
+    /// Create a cursor for a buffer that starts `base` bytes into the file.
+    pub fn with_offset(buf: &'a [u8], base: usize) -> Cursor<'a> {
         let buf = BitReadBuffer::new(buf, LittleEndian);
         let bit_pos = Cell::new(0);
-        Cursor { buf, bit_pos }
+        Cursor { buf, bit_pos, base }
     }
 
     /// TODO: rename this... into_chunk? chunk_from_self? new_chunk_from_self?
-    pub fn chunk_bytes(&'a self, amt: usize) -> anyhow::Result<Cursor<'a>> {
+    pub fn chunk_bytes(&'a self, amt: usize) -> Result<Cursor<'a>> {
+        let base = self.byte_pos();
         let buf = self.read_bytes(amt)?.to_vec();
         Ok(Cursor {
             buf: BitReadBuffer::from(buf),
             bit_pos: Cell::new(0),
+            base,
         })
     }
 
+    /// The current position, IN BYTES, from the start of the file.
+    /// Rounds down if we are in the middle of a byte.
+    pub fn byte_pos(&self) -> usize {
+        self.base + self.bit_pos.get() / 8
+    }
+
+    /// The current position, IN BITS, from the start of the file.
+    fn file_bit_pos(&self) -> usize {
+        self.base * 8 + self.bit_pos.get()
+    }
+
     pub fn remaining_bits(&self) -> usize {
-        self.buf
-            .bit_len()
-            .checked_sub(self.bit_pos.get())
-            .unwrap_or_default()
+        self.buf.bit_len().saturating_sub(self.bit_pos.get())
     }
 
     pub fn is_empty(&self) -> bool {
-        self.remaining_bits() > 0
+        self.remaining_bits() == 0
     }
 
     /// checks if the NUMBER OF BITS LEFT are more than requested.
-    fn check_bounds(&self, amt: usize) -> anyhow::Result<()> {
+    fn check_bounds(&self, amt: usize) -> Result<()> {
         if amt > self.remaining_bits() {
-            anyhow::bail!("Read out of bounds");
+            Err(DemoError::UnexpectedEof {
+                bit_offset: self.file_bit_pos(),
+                requested: amt,
+                remaining: self.remaining_bits(),
+            })
         } else {
             Ok(())
         }
     }
 
-    fn advance_bits(&self, amt: usize) -> anyhow::Result<()> {
+    /// Attach our position to an error from the bit buffer.
+    fn bit_err(&self, err: BitError) -> DemoError {
+        let bit_offset = self.file_bit_pos();
+        match err {
+            BitError::NotEnoughData {
+                requested,
+                bits_left,
+            } => DemoError::UnexpectedEof {
+                bit_offset,
+                requested,
+                remaining: bits_left,
+            },
+            source => DemoError::BitBuffer { bit_offset, source },
+        }
+    }
+
+    fn advance_bits(&self, amt: usize) -> Result<()> {
         self.check_bounds(amt)?;
         self.bit_pos.set(self.bit_pos.get() + amt);
         Ok(())
     }
 
-    pub fn read_bit_bool(&self) -> anyhow::Result<bool> {
-        let b = self.buf.read_bool(self.bit_pos.get())?;
+    pub fn read_bit_bool(&self) -> Result<bool> {
+        let b = self
+            .buf
+            .read_bool(self.bit_pos.get())
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(1)?;
         Ok(b)
     }
 
-    pub fn read_byte_bool(&self) -> anyhow::Result<bool> {
+    /// Reads `count` bits (at most 32) as an unsigned int.
+    pub fn read_ubits(&self, count: usize) -> Result<u32> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let n = self
+            .buf
+            .read_int(self.bit_pos.get(), count)
+            .map_err(|e| self.bit_err(e))?;
+        self.advance_bits(count)?;
+        Ok(n)
+    }
+
+    /// Reads `count` bits (at most 32) as a sign extended int.
+    pub fn read_sbits(&self, count: usize) -> Result<i32> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let n = self
+            .buf
+            .read_int(self.bit_pos.get(), count)
+            .map_err(|e| self.bit_err(e))?;
+        self.advance_bits(count)?;
+        Ok(n)
+    }
+
+    /// Reads the engine's `UBitVar`, a 6 bit header whose top 2 bits
+    /// say how many more bits to read for the rest of the number.
+    pub fn read_ubit_var(&self) -> Result<u32> {
+        let n = self.read_ubits(6)?;
+        Ok(match n & (16 | 32) {
+            16 => (n & 15) | (self.read_ubits(4)? << 4),
+            32 => (n & 15) | (self.read_ubits(8)? << 4),
+            48 => (n & 15) | (self.read_ubits(32 - 4)? << 4),
+            _ => n,
+        })
+    }
+
+    pub fn read_byte_bool(&self) -> Result<bool> {
         let n = self.read_u8()?;
         Ok(n != 0)
     }
 
-    pub fn read_u8(&self) -> anyhow::Result<u8> {
-        let n = self.buf.read_int(self.bit_pos.get(), 8)?;
+    pub fn read_u8(&self) -> Result<u8> {
+        let n = self
+            .buf
+            .read_int(self.bit_pos.get(), 8)
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(8)?;
         Ok(n)
     }
 
-    pub fn read_i8(&self) -> anyhow::Result<i8> {
-        let n = self.buf.read_int(self.bit_pos.get(), 8)?;
+    pub fn read_i8(&self) -> Result<i8> {
+        let n = self
+            .buf
+            .read_int(self.bit_pos.get(), 8)
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(8)?;
         Ok(n)
     }
 
-    pub fn read_u16(&self) -> anyhow::Result<u16> {
-        let n = self.buf.read_int(self.bit_pos.get(), 16)?;
+    pub fn read_u16(&self) -> Result<u16> {
+        let n = self
+            .buf
+            .read_int(self.bit_pos.get(), 16)
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(16)?;
         Ok(n)
     }
 
-    pub fn read_i16(&self) -> anyhow::Result<i16> {
-        let n = self.buf.read_int(self.bit_pos.get(), 16)?;
+    pub fn read_i16(&self) -> Result<i16> {
+        let n = self
+            .buf
+            .read_int(self.bit_pos.get(), 16)
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(16)?;
         Ok(n)
     }
 
-    pub fn read_u32(&self) -> anyhow::Result<u32> {
-        let n = self.buf.read_int(self.bit_pos.get(), 32)?;
+    pub fn read_u32(&self) -> Result<u32> {
+        let n = self
+            .buf
+            .read_int(self.bit_pos.get(), 32)
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(32)?;
         Ok(n)
     }
 
-    pub fn read_i32(&self) -> anyhow::Result<i32> {
-        let n = self.buf.read_int(self.bit_pos.get(), 32)?;
+    pub fn read_i32(&self) -> Result<i32> {
+        let n = self
+            .buf
+            .read_int(self.bit_pos.get(), 32)
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(32)?;
         Ok(n)
     }
 
-    pub fn read_f32(&self) -> anyhow::Result<f32> {
-        let n = self.buf.read_float(self.bit_pos.get())?;
+    pub fn read_f32(&self) -> Result<f32> {
+        let n = self
+            .buf
+            .read_float(self.bit_pos.get())
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(32)?;
         Ok(n)
     }
 
-    pub fn read_i64(&self) -> anyhow::Result<i64> {
-        let n = self.buf.read_int(self.bit_pos.get(), 64)?;
+    pub fn read_i64(&self) -> Result<i64> {
+        let n = self
+            .buf
+            .read_int(self.bit_pos.get(), 64)
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(64)?;
         Ok(n)
     }
 
-    pub fn read_bytes(&'a self, amt: usize) -> anyhow::Result<Cow<'a, [u8]>> {
-        let bytes = self.buf.read_bytes(self.bit_pos.get(), amt)?;
+    /// Reads a little endian i32 length prefix,
+    /// failing if it is negative (or zero, unless `allow_zero` is set).
+    pub fn read_length(&self, what: &'static str, allow_zero: bool) -> Result<usize> {
+        let byte_offset = self.byte_pos();
+        let length = self.read_i32()?;
+        if length < 0 || (length == 0 && !allow_zero) {
+            return Err(DemoError::BadLength {
+                what,
+                length,
+                byte_offset,
+            });
+        }
+        Ok(length as usize)
+    }
+
+    pub fn read_bytes(&'a self, amt: usize) -> Result<Cow<'a, [u8]>> {
+        let bytes = self
+            .buf
+            .read_bytes(self.bit_pos.get(), amt)
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(amt * 8)?;
         Ok(bytes)
     }
@@ -130,8 +253,11 @@ impl<'a> Cursor<'a> {
     /// Hello\0\0\0\0\0\0\0\0\0
     /// And we call read_cstr(buffer, 14), the resulting CStr
     /// will only be Hello. It will not read after the first null byte.
-    pub fn read_cstr(&'a self, length: usize) -> anyhow::Result<Cow<'a, str>> {
-        let s = self.buf.read_string(self.bit_pos.get(), Some(length))?;
+    pub fn read_cstr(&'a self, length: usize) -> Result<Cow<'a, str>> {
+        let s = self
+            .buf
+            .read_string(self.bit_pos.get(), Some(length))
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits(length * 8)?;
         Ok(s)
     }
@@ -139,18 +265,61 @@ impl<'a> Cursor<'a> {
     /* Read bytes from this cursor until we hit a null byte.
     Then, interpret the bytes read as a string.
     Currently panics if there is no null byte before the end of the buffer. */
-    pub fn read_cstr_until(&'a self) -> anyhow::Result<Cow<'a, str>> {
-        let s = self.buf.read_string(self.bit_pos.get(), None)?;
+    pub fn read_cstr_until(&'a self) -> Result<Cow<'a, str>> {
+        let s = self
+            .buf
+            .read_string(self.bit_pos.get(), None)
+            .map_err(|e| self.bit_err(e))?;
         self.advance_bits((s.len() + 1) * 8)?;
         Ok(s)
     }
 
+    /// Reads a protobuf style varint of up to 32 bits, byte by byte.
+    /// Like the engine, this gives up after 5 bytes, instead of failing.
+    pub fn read_var_u32(&self) -> Result<u32> {
+        let mut result = 0;
+        for count in 0..5 {
+            let b = self.read_u8()? as u32;
+            result |= (b & 0x7F) << (7 * count);
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Reads a protobuf style varint of up to 64 bits, byte by byte.
+    /// Like the engine, this gives up after 10 bytes, instead of failing.
+    pub fn read_var_u64(&self) -> Result<u64> {
+        let mut result = 0;
+        for count in 0..10 {
+            let b = self.read_u8()? as u64;
+            result |= (b & 0x7F) << (7 * count);
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// A zigzag encoded `read_var_u32`.
+    pub fn read_signed_var_i32(&self) -> Result<i32> {
+        let n = self.read_var_u32()?;
+        Ok((n >> 1) as i32 ^ -((n & 1) as i32))
+    }
+
+    /// A zigzag encoded `read_var_u64`.
+    pub fn read_signed_var_i64(&self) -> Result<i64> {
+        let n = self.read_var_u64()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
     /// TODO: Can we utilize prost for this?
     /// Reads a variable sized integer, like a protobuf...
     /// TBH idk, I found this in the CSGO-demos-manager code,
     /// And it seems like we need this when reading packets.
     /// https://github.com/akiver/CSGO-Demos-Manager/blob/1d0e062db854ae47889339c8f80656cfd55217f6/demoinfo/DemoInfo/BitStream/BitStreamUtil.cs#L115
-    pub fn read_protobuf_var_int(&self) -> anyhow::Result<i32> {
+    pub fn read_protobuf_var_int(&self) -> Result<i32> {
         let mut b: u8 = 0x80;
         let mut result: i32 = 0;
         let mut count = 0;
@@ -160,7 +329,9 @@ impl<'a> Cursor<'a> {
             if count < 4 || count == 4 && ((b & 0xF8) == 0 || (b & 0xF8) == 0xF8) {
                 result |= ((b & !0x80) as i32) << (7 * count);
             } else {
-                anyhow::bail!("Overflowing the variable sized int!");
+                return Err(DemoError::VarIntOverflow {
+                    bit_offset: self.file_bit_pos(),
+                });
             }
             count += 1;
         }
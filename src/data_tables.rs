@@ -1,37 +1,38 @@
 //! Wow this is an ugly file. Just wanted to put that up here at the top.
 //! I should split it into data_tables.rs and string_tables.rs
 
-use anyhow::Context;
+use std::collections::HashMap;
+
 use serde::Serialize;
 
 use crate::cursor::Cursor;
+use crate::error::{DemoError, Result};
 use crate::message::Message;
 use crate::packet;
 use crate::protos::csvc_msg_send_table::SendpropT;
 use crate::protos::CsvcMsgSendTable;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DataTable {
-    data_tables: Vec<CsvcMsgSendTable>,
+    pub data_tables: Vec<CsvcMsgSendTable>,
     pub server_classes: Vec<ServerClass>,
-    service_class_bits: u8,
+    pub service_class_bits: u8,
 }
 
 impl DataTable {
     fn gather_excludes(
         send_table: &CsvcMsgSendTable,
         all_tables: &[CsvcMsgSendTable],
-    ) -> anyhow::Result<Vec<SendpropT>> {
+    ) -> Result<Vec<SendpropT>> {
         let mut excludes = vec![];
         for prop in &send_table.props {
-            let flags = PropFlags::from_bits(prop.flags() as u32).context("Bad prop flags")?;
+            let flags = PropFlags::from_i32(prop.flags())?;
             if flags.contains(PropFlags::EXCLUDE) {
                 // TODO: make this a ref.
                 excludes.push(prop.clone())
             }
             if prop.r#type() == (PropTypes::DataTable as i32) {
-                let sub_table = Self::find_by_name(all_tables, prop.dt_name())
-                    .context("No Table Found with name.")?;
+                let sub_table = Self::find_by_name(all_tables, prop.dt_name())?;
                 let inner_excludes = Self::gather_excludes(sub_table, all_tables)?;
                 excludes.extend(inner_excludes);
             }
@@ -42,11 +43,14 @@ impl DataTable {
     fn find_by_name<'a>(
         all_tables: &'a [CsvcMsgSendTable],
         name: &str,
-    ) -> Option<&'a CsvcMsgSendTable> {
-        all_tables.iter().find(|t| t.net_table_name() == name)
+    ) -> Result<&'a CsvcMsgSendTable> {
+        all_tables
+            .iter()
+            .find(|t| t.net_table_name() == name)
+            .ok_or_else(|| DemoError::MissingSendTable(name.to_string()))
     }
 
-    pub fn parse(cursor: &Cursor) -> anyhow::Result<DataTable> {
+    pub fn parse(cursor: &Cursor) -> Result<DataTable> {
         let mut data_tables = vec![];
         // Start by parsing SendTable messages until one has is_end.
         loop {
@@ -59,7 +63,11 @@ impl DataTable {
                         data_tables.push(st);
                     }
                 }
-                _ => anyhow::bail!("Only SendTable messages allowed."),
+                _ => {
+                    return Err(DemoError::UnexpectedMessage {
+                        expected: "SendTable",
+                    })
+                }
             }
         }
 
@@ -69,10 +77,16 @@ impl DataTable {
             let sc = ServerClass::parse(cursor, server_class_count)?;
             server_classes.push(sc);
         }
+        DataTable::new(data_tables, server_classes)
+    }
 
+    /// Flatten the props of every server class from its send table.
+    pub fn new(
+        data_tables: Vec<CsvcMsgSendTable>,
+        mut server_classes: Vec<ServerClass>,
+    ) -> Result<DataTable> {
         for sc in &mut server_classes {
-            let send_table =
-                Self::find_by_name(&data_tables, &sc.owning_name).context("No owning table.")?;
+            let send_table = Self::find_by_name(&data_tables, &sc.owning_name)?;
             let excludes = DataTable::gather_excludes(send_table, &data_tables)?;
             sc.fill_props(&excludes, &data_tables)?;
             // let props = Self::gather_props(send_table, &data_tables, sc,
@@ -80,7 +94,7 @@ impl DataTable {
             // flatten(send_table, &data_tables, sc)?;
         }
 
-        let mut iter = server_class_count;
+        let mut iter = server_classes.len();
         let mut service_class_bits = 1;
         loop {
             iter >>= 1;
@@ -113,43 +127,75 @@ fn is_prop_excluded(
 
 // TODO: should this be named ServiceClass?
 // TODO: take &'a str.
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ServerClass {
     /// TODO: what is this exactly.
-    class_id: i16,
+    pub class_id: i16,
     /// The name of this ServerClass
-    name: String,
+    pub name: String,
     /// The name of the owning SendTable.
-    owning_name: String,
+    pub owning_name: String,
+    /// The flattened props, in field index order.
     pub props: Vec<Prop>,
-    array_props: Vec<Prop>,
+    pub array_props: Vec<Prop>,
+    /// The field index of each prop, by its path.
+    #[serde(skip)]
+    prop_indices: HashMap<String, usize>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Prop {
-    inner: SendpropT,
-    path: String,
+    pub inner: SendpropT,
+    pub path: String,
+    /// For array props, the template prop each element is decoded with.
+    pub element: Option<Box<SendpropT>>,
 }
 
 impl Prop {
     pub fn new(inner: SendpropT, path: String) -> Prop {
-        Prop { inner, path }
+        Prop {
+            inner,
+            path,
+            element: None,
+        }
     }
 }
 
 impl ServerClass {
+    /// `GatherProps` in the engine.
+    /// The props of this table are added to `out` only after the props of
+    /// any sub tables that are not collapsible.
     fn gather_props(
         send_table: &CsvcMsgSendTable,
         all_tables: &[CsvcMsgSendTable],
         excludes: &[SendpropT],
-        path: String,
-    ) -> anyhow::Result<(Vec<Prop>, Vec<Prop>)> {
+        path: &str,
+        out: &mut Vec<Prop>,
+        arr_out: &mut Vec<Prop>,
+    ) -> Result<()> {
         let mut store = Vec::with_capacity(send_table.props.len());
-        let mut arr_store = vec![];
+        Self::iterate_props(
+            send_table, all_tables, excludes, path, out, &mut store, arr_out,
+        )?;
+        out.extend(store);
+        Ok(())
+    }
 
+    /// `GatherProps_IterateProps` in the engine.
+    /// Collapsible sub tables are inlined into `store`,
+    /// other sub tables are gathered straight into `out`.
+    fn iterate_props(
+        send_table: &CsvcMsgSendTable,
+        all_tables: &[CsvcMsgSendTable],
+        excludes: &[SendpropT],
+        path: &str,
+        out: &mut Vec<Prop>,
+        store: &mut Vec<Prop>,
+        arr_out: &mut Vec<Prop>,
+    ) -> Result<()> {
         for i in 0..send_table.props.len() {
             let prop = &send_table.props[i];
-            let flags = PropFlags::from_bits(prop.flags() as u32).context("Bad Flag bits.")?;
+            let flags = PropFlags::from_i32(prop.flags())?;
             if flags.contains(PropFlags::INSIDE_ARRAY)
                 || flags.contains(PropFlags::EXCLUDE)
                 || is_prop_excluded(send_table, prop, excludes)
@@ -163,76 +209,131 @@ impl ServerClass {
                     p += prop.var_name();
                 }
                 if !p.is_empty() && !path.is_empty() {
-                    p = path.clone() + "." + &p;
+                    p = path.to_string() + "." + &p;
                 }
                 p
             };
 
             match PropTypes::from_i32(prop.r#type.unwrap_or(-1))? {
                 PropTypes::Array => {
-                    // TODO: C++ code pushes the path with it... Necessary?
-                    // Also how does the i-1 work here... everyone does it I guess...
-                    arr_store.push(Prop::new(
+                    // The element template is always the prop right before the array.
+                    arr_out.push(Prop::new(
                         send_table.props[i - 1].clone(),
                         prop_path.clone(),
                     ));
-                    store.push(Prop::new(prop.clone(), prop_path));
+                    let mut array = Prop::new(prop.clone(), prop_path);
+                    array.element = Some(Box::new(send_table.props[i - 1].clone()));
+                    store.push(array);
                 }
                 PropTypes::DataTable => {
-                    let table = DataTable::find_by_name(all_tables, prop.dt_name())
-                        .context("No table with name.")?;
-                    // TODO:
-                    // Other impls branch if this is collapsible... and then do basically the same
-                    // thing (AIUI) in both cases! WHY!?!!?
-                    let (new_store, new_arr_store) =
-                        Self::gather_props(table, all_tables, excludes, prop_path)?;
-                    store.extend(new_store);
-                    arr_store.extend(new_arr_store);
+                    let table = DataTable::find_by_name(all_tables, prop.dt_name())?;
+                    if flags.contains(PropFlags::COLLAPSIBLE) {
+                        Self::iterate_props(
+                            table, all_tables, excludes, &prop_path, out, store, arr_out,
+                        )?;
+                    } else {
+                        Self::gather_props(table, all_tables, excludes, &prop_path, out, arr_out)?;
+                    }
                 }
                 _ => store.push(Prop::new(prop.clone(), prop_path)),
             }
         }
-        Ok((store, arr_store))
+        Ok(())
+    }
+
+    /// Sort the flattened props by priority, like the engine does before
+    /// field indices are assigned. `CHANGES_OFTEN` props count as priority 64.
+    /// This swap sort is not stable, so it has to match the engine exactly.
+    fn sort_by_priority(props: &mut [Prop]) {
+        let mut priorities = vec![64];
+        for prop in props.iter() {
+            if !priorities.contains(&prop.inner.priority()) {
+                priorities.push(prop.inner.priority());
+            }
+        }
+        priorities.sort_unstable();
+
+        let changes_often = PropFlags::CHANGES_OFTEN.bits() as i32;
+        let mut start = 0;
+        for priority in priorities {
+            while let Some(found) = (start..props.len()).find(|&i| {
+                let prop = &props[i].inner;
+                prop.priority() == priority || (priority == 64 && prop.flags() & changes_often != 0)
+            }) {
+                props.swap(start, found);
+                start += 1;
+            }
+        }
     }
 
     pub fn fill_props(
         &mut self,
         excludes: &[SendpropT],
         tables: &[CsvcMsgSendTable],
-    ) -> anyhow::Result<()> {
-        let owning_table =
-            DataTable::find_by_name(tables, &self.owning_name).context("No DataTable found.")?;
-        let (props, array_props) =
-            Self::gather_props(owning_table, tables, excludes, String::new())?;
+    ) -> Result<()> {
+        let owning_table = DataTable::find_by_name(tables, &self.owning_name)?;
+        let mut props = vec![];
+        let mut array_props = vec![];
+        Self::gather_props(
+            owning_table,
+            tables,
+            excludes,
+            "",
+            &mut props,
+            &mut array_props,
+        )?;
+        Self::sort_by_priority(&mut props);
+        self.prop_indices = props
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.path.clone(), i))
+            .collect();
         self.props = props;
         self.array_props = array_props;
         Ok(())
     }
 
-    pub fn parse(cursor: &Cursor, class_count: i16) -> anyhow::Result<ServerClass> {
+    /// The prop with the given field index,
+    /// as used in entity updates.
+    pub fn prop(&self, index: usize) -> Option<&Prop> {
+        self.props.get(index)
+    }
+
+    /// Find the field index of a prop, by its full path.
+    pub fn prop_index(&self, path: &str) -> Option<usize> {
+        self.prop_indices.get(path).copied()
+    }
+
+    pub fn parse(cursor: &Cursor, class_count: i16) -> Result<ServerClass> {
         let class_id = cursor.read_i16()?;
         if class_id > class_count {
-            anyhow::bail!("Invalid class index... {class_id} > {class_count}");
+            return Err(DemoError::InvalidClassId {
+                class_id,
+                class_count,
+            });
         }
 
         let name = cursor.read_cstr_until()?.to_string();
         let owning_name = cursor.read_cstr_until()?.to_string();
+        Ok(ServerClass::new(class_id, name, owning_name))
+    }
 
-        let props = vec![];
-        let array_props = vec![];
-        Ok(ServerClass {
+    /// A class without any props, until [`ServerClass::fill_props`].
+    pub fn new(class_id: i16, name: String, owning_name: String) -> ServerClass {
+        ServerClass {
             class_id,
             name,
             owning_name,
-            props,
-            array_props,
-        })
+            props: vec![],
+            array_props: vec![],
+            prop_indices: HashMap::new(),
+        }
     }
 }
 
 /// An enumeration that is used to detect the type.
 /// Just cast this to i32 to get the value as its used in the protobuf.
-enum PropTypes {
+pub enum PropTypes {
     Int = 0,
     Float,
     Vector,
@@ -241,11 +342,12 @@ enum PropTypes {
     Array, // An array of the base types (can't be of datatables).
     DataTable,
     Int64,
+    #[allow(clippy::enum_variant_names)]
     NUMSendPropTypes,
 }
 
 impl PropTypes {
-    pub fn from_i32(i: i32) -> anyhow::Result<PropTypes> {
+    pub fn from_i32(i: i32) -> Result<PropTypes> {
         Ok(match i {
             0 => PropTypes::Int,
             1 => PropTypes::Float,
@@ -256,13 +358,13 @@ impl PropTypes {
             6 => PropTypes::DataTable,
             7 => PropTypes::Int64,
             8 => PropTypes::NUMSendPropTypes,
-            _ => anyhow::bail!("Bad Prop Type value `{i}`."),
+            _ => return Err(DemoError::BadPropType(i)),
         })
     }
 }
 
 bitflags::bitflags! {
-    struct PropFlags: u32 {
+    pub struct PropFlags: u32 {
         /// // Unsigned integer data.
         const UNSIGNED = 0b00000000000000000001;
         /// If this is set, the float/vector is treated like a world coordinate. Note that the bit count is ignored in this case.
@@ -305,3 +407,166 @@ bitflags::bitflags! {
         const VARINT = 0b10000000000000000000;
     }
 }
+
+impl PropFlags {
+    pub fn from_i32(flags: i32) -> Result<PropFlags> {
+        PropFlags::from_bits(flags as u32).ok_or(DemoError::BadPropFlags(flags))
+    }
+}
+
+/// Send tables for tests, built the way the server describes them.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    pub fn prop(name: &str, kind: PropTypes, flags: PropFlags, priority: i32) -> SendpropT {
+        SendpropT {
+            r#type: Some(kind as i32),
+            var_name: Some(name.to_string()),
+            flags: Some(flags.bits() as i32),
+            priority: Some(priority),
+            ..SendpropT::default()
+        }
+    }
+
+    /// An unsigned int of `bits` bits.
+    pub fn int(name: &str, bits: i32, priority: i32) -> SendpropT {
+        SendpropT {
+            num_bits: Some(bits),
+            ..prop(name, PropTypes::Int, PropFlags::UNSIGNED, priority)
+        }
+    }
+
+    /// A prop pointing at another table, or at a prop to exclude from it.
+    pub fn table_prop(name: &str, dt_name: &str, flags: PropFlags) -> SendpropT {
+        SendpropT {
+            dt_name: Some(dt_name.to_string()),
+            ..prop(name, PropTypes::DataTable, flags, 128)
+        }
+    }
+
+    pub fn send_table(name: &str, props: Vec<SendpropT>) -> CsvcMsgSendTable {
+        CsvcMsgSendTable {
+            net_table_name: Some(name.to_string()),
+            props,
+            ..CsvcMsgSendTable::default()
+        }
+    }
+
+    /// A player class over `DT_Player`, which has a collapsible base class,
+    /// an exclude, a prop that changes often, a sub table and an array.
+    /// Its props flatten to, by field index:
+    /// `m_flSimulationTime`, `m_vecOrigin`, `m_iTeamNum`, `m_local.m_iAmmo`,
+    /// `m_iHealth`, `m_local.m_nTickBase`.
+    pub fn player_table() -> DataTable {
+        let exclude = SendpropT {
+            dt_name: Some("DT_BaseEntity".to_string()),
+            ..prop("m_nModelIndex", PropTypes::Int, PropFlags::EXCLUDE, 128)
+        };
+        let origin = SendpropT {
+            num_bits: Some(32),
+            ..prop(
+                "m_vecOrigin",
+                PropTypes::VectorXY,
+                PropFlags::NO_SCALE | PropFlags::CHANGES_OFTEN,
+                128,
+            )
+        };
+        let ammo_element = SendpropT {
+            num_bits: Some(8),
+            ..prop(
+                "000",
+                PropTypes::Int,
+                PropFlags::UNSIGNED | PropFlags::INSIDE_ARRAY,
+                128,
+            )
+        };
+        let ammo = SendpropT {
+            num_elements: Some(4),
+            ..prop("m_iAmmo", PropTypes::Array, PropFlags::empty(), 128)
+        };
+        let tables = vec![
+            send_table(
+                "DT_BaseEntity",
+                vec![
+                    int("m_iTeamNum", 2, 128),
+                    int("m_nModelIndex", 11, 128),
+                    SendpropT {
+                        num_bits: Some(32),
+                        ..prop(
+                            "m_flSimulationTime",
+                            PropTypes::Float,
+                            PropFlags::NO_SCALE,
+                            1,
+                        )
+                    },
+                ],
+            ),
+            send_table(
+                "DT_Local",
+                vec![ammo_element, ammo, int("m_nTickBase", 16, 128)],
+            ),
+            send_table(
+                "DT_Player",
+                vec![
+                    table_prop("baseclass", "DT_BaseEntity", PropFlags::COLLAPSIBLE),
+                    exclude,
+                    int("m_iHealth", 7, 128),
+                    origin,
+                    table_prop("m_local", "DT_Local", PropFlags::empty()),
+                ],
+            ),
+        ];
+        let class = ServerClass::new(0, "CCSPlayer".to_string(), "DT_Player".to_string());
+        DataTable::new(tables, vec![class]).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flattened_prop_order() {
+        let data_table = fixtures::player_table();
+        let class = &data_table.server_classes[0];
+        let paths: Vec<_> = class.props.iter().map(|p| p.path.as_str()).collect();
+        // Sub tables come before the props of the table holding them,
+        // collapsible ones inline. Then lower priorities come first,
+        // `CHANGES_OFTEN` counting as 64. The swaps that move those to the
+        // front send the first props gathered to the back.
+        assert_eq!(
+            paths,
+            [
+                "m_flSimulationTime",
+                "m_vecOrigin",
+                "m_iTeamNum",
+                "m_local.m_iAmmo",
+                "m_iHealth",
+                "m_local.m_nTickBase",
+            ]
+        );
+        assert_eq!(class.prop_index("m_iHealth"), Some(4));
+        assert_eq!(class.prop_index("m_nModelIndex"), None);
+
+        let ammo = &class.props[3];
+        assert_eq!(ammo.element.as_ref().unwrap().var_name(), "000");
+        assert_eq!(class.array_props.len(), 1);
+        assert_eq!(data_table.service_class_bits, 1);
+    }
+
+    #[test]
+    fn missing_send_table() {
+        let tables = vec![fixtures::send_table(
+            "DT_Player",
+            vec![fixtures::table_prop(
+                "m_local",
+                "DT_Local",
+                PropFlags::empty(),
+            )],
+        )];
+        let class = ServerClass::new(0, "CCSPlayer".to_string(), "DT_Player".to_string());
+        let err = DataTable::new(tables, vec![class]).unwrap_err();
+        assert!(matches!(err, DemoError::MissingSendTable(name) if name == "DT_Local"));
+    }
+}
@@ -3,35 +3,54 @@ use std::borrow::Cow;
 use serde::Serialize;
 
 use crate::cursor::Cursor;
+use crate::error::{DemoError, Result};
 use crate::frame::Frame;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DemoHeader<'a> {
     /// Demo protocol version (stored in little endian)
-    demo_protocol: i32,
+    pub demo_protocol: i32,
     /// Network protocol version number (stored in little endian)
-    network_protocol: u32,
+    pub network_protocol: u32,
     /// Max 259 characters (source is a 260 byte C-string)
-    server_name: Cow<'a, str>,
+    pub server_name: Cow<'a, str>,
     /// Max 259 characters (source is a 260 byte C-string)
-    client_name: Cow<'a, str>,
+    pub client_name: Cow<'a, str>,
     /// Max 259 characters (source is a 260 byte C-string)
-    map_name: Cow<'a, str>,
+    pub map_name: Cow<'a, str>,
     /// Max 259 characters (source is a 260 byte C-string)
-    game_directory: Cow<'a, str>,
+    pub game_directory: Cow<'a, str>,
     /// The length of the demo, in seconds
-    playback_time: f32,
+    pub playback_time: f32,
     /// The number of ticks in the demo
-    ticks: i32,
+    pub ticks: i32,
     /// The number of frames in the demo
-    frames: i32,
+    pub frames: i32,
     /// Length of the signon data (Init for first frame)
-    sign_on_length: i32,
+    pub sign_on_length: i32,
 }
 
 impl<'a> DemoHeader<'a> {
-    pub fn parse<'b: 'a>(data: &'b Cursor) -> anyhow::Result<DemoHeader<'a>> {
-        assert_eq!(b"HL2DEMO\x00", data.read_bytes(8)?.as_ref());
+    /// Detach the header from the buffer it was parsed from.
+    pub fn into_owned(self) -> DemoHeader<'static> {
+        DemoHeader {
+            server_name: Cow::Owned(self.server_name.into_owned()),
+            client_name: Cow::Owned(self.client_name.into_owned()),
+            map_name: Cow::Owned(self.map_name.into_owned()),
+            game_directory: Cow::Owned(self.game_directory.into_owned()),
+            ..self
+        }
+    }
+
+    pub fn parse<'b: 'a>(data: &'b Cursor) -> Result<DemoHeader<'a>> {
+        let byte_offset = data.byte_pos();
+        let magic = data.read_bytes(8)?;
+        if magic.as_ref() != b"HL2DEMO\x00" {
+            return Err(DemoError::BadMagic {
+                found: magic.into_owned(),
+                byte_offset,
+            });
+        }
         let demo_protocol = data.read_i32()?;
         let network_protocol = data.read_u32()?;
         let server_name = data.read_cstr(260)?;
@@ -57,6 +76,8 @@ impl<'a> DemoHeader<'a> {
     }
 }
 
+/// A fully parsed demo, with every frame held in memory.
+/// Prefer [`DemoReader`] for large demos, as it decodes frames one at a time.
 #[derive(Debug, Serialize)]
 pub struct Demo<'a> {
     pub header: DemoHeader<'a>,
@@ -64,18 +85,61 @@ pub struct Demo<'a> {
 }
 
 impl<'a> Demo<'a> {
-    pub fn parse(cursor: &'a Cursor) -> anyhow::Result<Demo<'a>> {
+    pub fn parse(cursor: &'a Cursor) -> Result<Demo<'a>> {
+        let reader = DemoReader::new(cursor)?;
+        let frames = reader.frames().collect::<Result<Vec<_>>>()?;
+        Ok(Demo {
+            header: reader.header,
+            frames,
+        })
+    }
+}
+
+/// A streaming demo reader.
+/// The header is parsed up front, and the frames are decoded lazily,
+/// so memory use stays bounded no matter how long the demo is.
+#[derive(Debug)]
+pub struct DemoReader<'a> {
+    pub header: DemoHeader<'a>,
+    cursor: &'a Cursor<'a>,
+}
+
+impl<'a> DemoReader<'a> {
+    pub fn new(cursor: &'a Cursor) -> Result<DemoReader<'a>> {
         let header = DemoHeader::parse(cursor)?;
-        let mut frames = Vec::new();
-        for i in 0..header.frames {
-            let frame = Frame::parse(cursor)?;
-            let is_last = frame.is_last();
-            frames.push(frame);
-            if is_last {
-                println!("Got last frame {i}/{}", header.frames);
-                break;
-            }
+        Ok(DemoReader { header, cursor })
+    }
+
+    /// Iterate over the frames following the header.
+    /// Each call starts from wherever the previous iterator left off.
+    pub fn frames(&self) -> FrameIter<'a> {
+        FrameIter {
+            cursor: self.cursor,
+            done: false,
+        }
+    }
+}
+
+/// Yields frames one at a time until the `Stop` frame, or the end of the data.
+/// After an error is returned, the iterator is exhausted.
+#[derive(Debug)]
+pub struct FrameIter<'a> {
+    cursor: &'a Cursor<'a>,
+    done: bool,
+}
+
+impl<'a> Iterator for FrameIter<'a> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.cursor.remaining_bits() == 0 {
+            return None;
         }
-        Ok(Demo { header, frames })
+        let frame = Frame::parse(self.cursor);
+        self.done = match &frame {
+            Ok(frame) => frame.is_last(),
+            Err(_) => true,
+        };
+        Some(frame)
     }
 }
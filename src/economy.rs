@@ -0,0 +1,501 @@
+//! What each team had to spend every round, and what they bought with it.
+//! Feed an [`EconomyTracker`] every event, and the [`GameState`] and current
+//! round after every frame. Money and equipment are read from the player
+//! entities once, when the freeze time ends.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::events::{DemoEvent, Event};
+use crate::game_state::GameState;
+use crate::positions::{class_name, weapon_name, PlayerPosition};
+use crate::props::PropValue;
+use crate::roster::{TEAM_CT, TEAM_T};
+use crate::rounds::{
+    GameRules, GameRulesReader, Round, GAME_PHASE_FIRST_HALF, GAME_PHASE_HALFTIME,
+    GAME_PHASE_SECOND_HALF,
+};
+use crate::steam_id::SteamId;
+
+/// `MAX_WEAPONS` in the engine, the length of `m_hMyWeapons`.
+const MAX_WEAPONS: usize = 64;
+/// Below this average equipment value per player, a team is saving.
+const ECO_EQUIPMENT: i32 = 1000;
+/// From this average equipment value per player, a team has bought rifles and armor.
+const FULL_EQUIPMENT: i32 = 4000;
+/// A team left with less than this on average per player has spent all it could.
+const FORCE_MONEY_LEFT: i32 = 1000;
+
+/// How a team bought, going by the money and equipment of its players.
+/// The thresholds are averages per player, so they hold for any team size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuyType {
+    /// The first round of either half in regulation, when everyone starts from the same money.
+    Pistol,
+    /// Equipment worth less than $1000 a player.
+    #[default]
+    Eco,
+    /// Everything spent, but short of a full buy.
+    Force,
+    /// Some bought, some saved.
+    Half,
+    /// Equipment worth $4000 a player or more.
+    Full,
+}
+
+impl BuyType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BuyType::Pistol => "pistol",
+            BuyType::Eco => "eco",
+            BuyType::Force => "force",
+            BuyType::Half => "half",
+            BuyType::Full => "full",
+        }
+    }
+
+    fn classify(players: i32, pistol: bool, money: i32, equipment_value: i32) -> BuyType {
+        let players = players.max(1);
+        if pistol {
+            BuyType::Pistol
+        } else if equipment_value / players >= FULL_EQUIPMENT {
+            BuyType::Full
+        } else if equipment_value / players < ECO_EQUIPMENT {
+            BuyType::Eco
+        } else if money / players < FORCE_MONEY_LEFT {
+            BuyType::Force
+        } else {
+            BuyType::Half
+        }
+    }
+}
+
+/// A team's economy in one round, summed over its players.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EconomyRow {
+    pub round: u32,
+    /// 2 for T and 3 for CT.
+    pub team: i32,
+    pub players: u32,
+    /// Money at the start of the round, from `m_iStartAccount`.
+    pub start_money: i32,
+    /// From `m_unRoundStartEquipmentValue`.
+    pub round_start_equipment_value: i32,
+    /// Money left at freeze end, from `m_iAccount`.
+    pub money: i32,
+    pub spent: i32,
+    /// Equipment value at freeze end, from `m_unCurrentEquipmentValue`.
+    pub equipment_value: i32,
+    pub buy: BuyType,
+}
+
+/// A player's economy in one round.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlayerEconomyRow {
+    pub round: u32,
+    pub user_id: Option<i32>,
+    pub steam_id: Option<SteamId>,
+    pub name: String,
+    pub team: i32,
+    pub start_money: i32,
+    pub round_start_equipment_value: i32,
+    pub money: i32,
+    /// Start money less money at freeze end. Drops from teammates make it lower.
+    pub spent: i32,
+    pub equipment_value: i32,
+    /// Weapons, grenades, and `vest`, `vesthelm` or `defuser`, at freeze end,
+    /// separated by spaces.
+    pub inventory: String,
+    /// Everything the player bought in the round, from `item_purchase` events,
+    /// separated by spaces.
+    pub purchases: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EconomyTracker {
+    players: Vec<PlayerEconomyRow>,
+    /// `(round, userid, item)`, matched up with the rows when finishing,
+    /// as most purchases come before the freeze end rows exist.
+    purchases: Vec<(u32, i32, String)>,
+    /// The last round read at freeze end.
+    read_round: u32,
+    /// The field indices of `m_hMyWeapons`, by player class id.
+    weapon_fields: HashMap<usize, Vec<usize>>,
+    rules: GameRulesReader,
+    /// The game phase at the last freeze end.
+    game_phase: i32,
+    /// Rounds that were the first of a half.
+    pistol_rounds: HashSet<u32>,
+}
+
+impl EconomyTracker {
+    pub fn new() -> EconomyTracker {
+        EconomyTracker::default()
+    }
+
+    /// `round` is the number from the [`RoundTracker`](crate::rounds::RoundTracker)
+    /// after it has seen the event.
+    pub fn handle_event(&mut self, event: &DemoEvent, state: &GameState, round: u32) {
+        let Event::ItemPurchase(purchase) = &event.event else {
+            return;
+        };
+        // Rows name players by their `userid` at freeze end, which a reconnect changes.
+        let user_id = state
+            .roster
+            .by_user_id(purchase.userid)
+            .map_or(purchase.userid, |p| p.user_id);
+        self.purchases
+            .push((round, user_id, purchase.weapon.clone()));
+    }
+
+    /// Read everyone's money and equipment if `round`'s freeze time has just ended.
+    pub fn update(&mut self, state: &GameState, round: Option<&Round>) {
+        let Some(round) = round else {
+            return;
+        };
+        if round.freeze_end_tick.is_none() || round.number == self.read_round {
+            return;
+        }
+        self.read_round = round.number;
+        if self
+            .rules
+            .read(state)
+            .is_some_and(|rules| self.starts_half(rules))
+        {
+            self.pistol_rounds.insert(round.number);
+        }
+        let tick = round.freeze_end_tick.unwrap_or_default();
+        for position in PlayerPosition::all(state, tick) {
+            if position.team != TEAM_T && position.team != TEAM_CT {
+                continue;
+            }
+            let row = player_row(state, &position, round.number, &mut self.weapon_fields);
+            self.players.extend(row);
+        }
+    }
+
+    /// Whether `rules`, read at a freeze end, are from the first round of a
+    /// half: the first of the match, or the first after halftime. Overtime
+    /// halves start with money for a full buy, so they don't count.
+    fn starts_half(&mut self, rules: GameRules) -> bool {
+        let last_phase = std::mem::replace(&mut self.game_phase, rules.game_phase);
+        if rules.warmup {
+            return false;
+        }
+        let halftime = rules.game_phase == GAME_PHASE_SECOND_HALF
+            && matches!(last_phase, GAME_PHASE_FIRST_HALF | GAME_PHASE_HALFTIME)
+            && rules.overtime_playing == 0;
+        rules.total_rounds_played == 0 || halftime
+    }
+
+    /// The team and player tables for the rounds that count, renumbered.
+    /// `numbers` maps the tracker's round numbers to theirs, as
+    /// [`RoundTracker::finish`](crate::rounds::RoundTracker::finish) returns them.
+    pub fn finish(
+        mut self,
+        numbers: &HashMap<u32, u32>,
+    ) -> (Vec<EconomyRow>, Vec<PlayerEconomyRow>) {
+        for (round, user_id, item) in self.purchases {
+            let row = self
+                .players
+                .iter_mut()
+                .find(|r| r.round == round && r.user_id == Some(user_id));
+            if let Some(row) = row {
+                if !row.purchases.is_empty() {
+                    row.purchases.push(' ');
+                }
+                row.purchases.push_str(&item);
+            }
+        }
+        let pistol_rounds: HashSet<u32> = self
+            .pistol_rounds
+            .iter()
+            .filter_map(|round| numbers.get(round).copied())
+            .collect();
+        let players: Vec<_> = self
+            .players
+            .into_iter()
+            .filter_map(|mut row| {
+                row.round = *numbers.get(&row.round)?;
+                Some(row)
+            })
+            .collect();
+
+        let mut teams: Vec<EconomyRow> = vec![];
+        for p in &players {
+            let existing = teams
+                .iter()
+                .position(|t| t.round == p.round && t.team == p.team);
+            let index = match existing {
+                Some(index) => index,
+                None => {
+                    teams.push(EconomyRow {
+                        round: p.round,
+                        team: p.team,
+                        players: 0,
+                        start_money: 0,
+                        round_start_equipment_value: 0,
+                        money: 0,
+                        spent: 0,
+                        equipment_value: 0,
+                        buy: BuyType::Eco,
+                    });
+                    teams.len() - 1
+                }
+            };
+            let team = &mut teams[index];
+            team.players += 1;
+            team.start_money += p.start_money;
+            team.round_start_equipment_value += p.round_start_equipment_value;
+            team.money += p.money;
+            team.spent += p.spent;
+            team.equipment_value += p.equipment_value;
+        }
+        for team in &mut teams {
+            team.buy = BuyType::classify(
+                team.players as i32,
+                pistol_rounds.contains(&team.round),
+                team.money,
+                team.equipment_value,
+            );
+        }
+        teams.sort_by_key(|t| (t.round, t.team));
+        (teams, players)
+    }
+}
+
+fn player_row(
+    state: &GameState,
+    position: &PlayerPosition,
+    round: u32,
+    weapon_fields: &mut HashMap<usize, Vec<usize>>,
+) -> Option<PlayerEconomyRow> {
+    let data_table = state.data_table.as_ref()?;
+    let entity = state.entities.get(position.entity_index)?;
+    let class = data_table.server_classes.get(entity.class_id)?;
+    let int = |path: &str| {
+        entity
+            .prop(class, path)
+            .and_then(PropValue::as_int)
+            .unwrap_or_default()
+    };
+
+    let weapon_fields = weapon_fields.entry(entity.class_id).or_insert_with(|| {
+        (0..MAX_WEAPONS)
+            .filter_map(|i| class.prop_index(&format!("m_hMyWeapons.{i:03}")))
+            .collect()
+    });
+    let mut inventory: Vec<String> = weapon_fields
+        .iter()
+        .filter_map(|&field| entity.props.get(field)?.as_ref())
+        .filter_map(PropValue::as_int)
+        .filter_map(crate::entities::handle_index)
+        .filter_map(|index| state.entities.get(index))
+        .filter_map(|weapon| class_name(data_table, weapon))
+        .map(weapon_name)
+        .collect();
+    if int("m_ArmorValue") > 0 {
+        let vest = if int("m_bHasHelmet") != 0 {
+            "vesthelm"
+        } else {
+            "vest"
+        };
+        inventory.push(vest.to_string());
+    }
+    if int("m_bHasDefuser") != 0 {
+        inventory.push("defuser".to_string());
+    }
+
+    let start_money = int("m_iStartAccount");
+    let money = int("m_iAccount");
+    Some(PlayerEconomyRow {
+        round,
+        user_id: position.user_id,
+        steam_id: position.steam_id,
+        name: position.name.clone(),
+        team: position.team,
+        start_money,
+        round_start_equipment_value: int("m_unRoundStartEquipmentValue"),
+        money,
+        spent: (start_money - money).max(0),
+        equipment_value: int("m_unCurrentEquipmentValue"),
+        inventory: inventory.join(" "),
+        purchases: String::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ItemPurchase;
+
+    #[test]
+    fn buy_types() {
+        // (players, pistol, money left, equipment value, buy)
+        let cases = [
+            (5, true, 0, 5 * 4000, BuyType::Pistol),
+            (5, false, 0, 5 * 4000, BuyType::Full),
+            (5, false, 5 * 3000, 5 * 4000, BuyType::Full),
+            (5, false, 0, 5 * 4000 - 1, BuyType::Force),
+            (5, false, 5 * 1000 - 1, 5 * 1000, BuyType::Force),
+            (5, false, 5 * 1000, 5 * 1000, BuyType::Half),
+            (5, false, 5 * 5000, 5 * 3000, BuyType::Half),
+            (5, false, 0, 5 * 1000 - 5, BuyType::Eco),
+            (5, false, 5 * 8000, 5 * 200, BuyType::Eco),
+            // The thresholds are per player.
+            (2, false, 0, 2 * 4000, BuyType::Full),
+            (2, false, 0, 5 * 1000, BuyType::Force),
+            (2, false, 0, 2 * 1000 - 2, BuyType::Eco),
+            // Rich teams still play pistol rounds, like after a restart.
+            (5, true, 5 * 16000, 5 * 200, BuyType::Pistol),
+            (0, false, 0, 0, BuyType::Eco),
+        ];
+        for (players, pistol, money, equipment_value, buy) in cases {
+            assert_eq!(
+                BuyType::classify(players, pistol, money, equipment_value),
+                buy,
+                "{players} players, pistol {pistol}, ${money} left, ${equipment_value} equipment",
+            );
+        }
+    }
+
+    fn rules(total_rounds_played: i32, game_phase: i32, overtime_playing: i32) -> GameRules {
+        GameRules {
+            total_rounds_played,
+            game_phase,
+            overtime_playing,
+            ..GameRules::default()
+        }
+    }
+
+    #[test]
+    fn pistol_rounds() {
+        let mut tracker = EconomyTracker::new();
+        let warmup = GameRules {
+            warmup: true,
+            ..rules(0, 0, 0)
+        };
+        let freeze_ends = [
+            (warmup, false),
+            (rules(0, GAME_PHASE_FIRST_HALF, 0), true),
+            (rules(1, GAME_PHASE_FIRST_HALF, 0), false),
+            (rules(14, GAME_PHASE_FIRST_HALF, 0), false),
+            (rules(15, GAME_PHASE_SECOND_HALF, 0), true),
+            (rules(16, GAME_PHASE_SECOND_HALF, 0), false),
+            (rules(30, GAME_PHASE_FIRST_HALF, 1), false),
+            (rules(33, GAME_PHASE_SECOND_HALF, 1), false),
+            // A restart after a knife round.
+            (rules(0, GAME_PHASE_FIRST_HALF, 0), true),
+        ];
+        for (i, (rules, pistol)) in freeze_ends.into_iter().enumerate() {
+            assert_eq!(tracker.starts_half(rules), pistol, "freeze end {i}");
+        }
+
+        // Halftime can be the phase at the freeze end before the second half.
+        let mut tracker = EconomyTracker::new();
+        assert!(!tracker.starts_half(rules(14, GAME_PHASE_HALFTIME, 0)));
+        assert!(tracker.starts_half(rules(15, GAME_PHASE_SECOND_HALF, 0)));
+        // A demo that starts in the second half doesn't know it just began.
+        let mut tracker = EconomyTracker::new();
+        assert!(!tracker.starts_half(rules(20, GAME_PHASE_SECOND_HALF, 0)));
+    }
+
+    fn player(
+        round: u32,
+        user_id: i32,
+        team: i32,
+        start_money: i32,
+        money: i32,
+    ) -> PlayerEconomyRow {
+        PlayerEconomyRow {
+            round,
+            user_id: Some(user_id),
+            name: format!("player{user_id}"),
+            team,
+            start_money,
+            money,
+            spent: start_money - money,
+            equipment_value: 200 + start_money - money,
+            ..PlayerEconomyRow::default()
+        }
+    }
+
+    fn purchase(tracker: &mut EconomyTracker, round: u32, userid: i32, weapon: &str) {
+        let event = DemoEvent {
+            tick: round * 100,
+            event: Event::ItemPurchase(ItemPurchase {
+                userid,
+                team: 0,
+                weapon: weapon.to_string(),
+            }),
+        };
+        tracker.handle_event(&event, &GameState::default(), round);
+    }
+
+    /// Three rounds with one T and two CTs, where round 1 was a knife round.
+    fn tracker() -> EconomyTracker {
+        let mut tracker = EconomyTracker::new();
+        for round in 1..=3 {
+            tracker.players.extend([
+                player(round, 1, TEAM_T, 800, 0),
+                player(round, 2, TEAM_CT, 5000, 1000),
+                player(round, 3, TEAM_CT, 5000, 4800),
+            ]);
+        }
+        tracker.pistol_rounds.insert(2);
+        purchase(&mut tracker, 2, 1, "weapon_glock");
+        purchase(&mut tracker, 2, 1, "item_kevlar");
+        purchase(&mut tracker, 3, 2, "weapon_ak47");
+        purchase(&mut tracker, 3, 3, "weapon_deagle");
+        // Nobody in the rows has this `userid`.
+        purchase(&mut tracker, 3, 4, "weapon_awp");
+        tracker
+    }
+
+    #[test]
+    fn purchases_match_rows() {
+        let numbers = HashMap::from([(2, 1), (3, 2)]);
+        let (_, players) = tracker().finish(&numbers);
+        let purchases: Vec<(u32, Option<i32>, &str)> = players
+            .iter()
+            .map(|p| (p.round, p.user_id, p.purchases.as_str()))
+            .collect();
+        assert_eq!(
+            purchases,
+            [
+                (1, Some(1), "weapon_glock item_kevlar"),
+                (1, Some(2), ""),
+                (1, Some(3), ""),
+                (2, Some(1), ""),
+                (2, Some(2), "weapon_ak47"),
+                (2, Some(3), "weapon_deagle"),
+            ]
+        );
+    }
+
+    #[test]
+    fn team_rows() {
+        let numbers = HashMap::from([(2, 1), (3, 2)]);
+        let (teams, _) = tracker().finish(&numbers);
+        let summary: Vec<_> = teams
+            .iter()
+            .map(|t| (t.round, t.team, t.players, t.buy))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, TEAM_T, 1, BuyType::Pistol),
+                (1, TEAM_CT, 2, BuyType::Pistol),
+                (2, TEAM_T, 1, BuyType::Force),
+                (2, TEAM_CT, 2, BuyType::Half),
+            ]
+        );
+        let ct = &teams[3];
+        assert_eq!(ct.start_money, 10000);
+        assert_eq!(ct.money, 5800);
+        assert_eq!(ct.spent, 4200);
+        assert_eq!(ct.equipment_value, 4600);
+    }
+}
@@ -0,0 +1,415 @@
+//! Decoding of the entity state carried in `CSVCMsg_PacketEntities`.
+//! Each message is a list of changes against the entities we already know about,
+//! so the `EntityTable` has to be fed every message, in order.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::cursor::Cursor;
+use crate::data_tables::{DataTable, ServerClass};
+use crate::error::{DemoError, Result};
+use crate::props::PropValue;
+use crate::protos::CsvcMsgPacketEntities;
+
+/// `MAX_EDICTS` in the engine.
+pub const MAX_ENTITIES: usize = 1 << 11;
+/// `NUM_NETWORKED_EHANDLE_SERIAL_NUMBER_BITS` in the engine.
+const ENTITY_SERIAL_BITS: usize = 10;
+
+/// The entity index in an entity handle prop, like `m_hOwnerEntity`.
+/// `None` for an empty handle.
+pub fn handle_index(handle: i32) -> Option<usize> {
+    let index = handle as usize & (MAX_ENTITIES - 1);
+    (index != MAX_ENTITIES - 1).then_some(index)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entity {
+    pub index: usize,
+    /// Index into `DataTable::server_classes`.
+    pub class_id: usize,
+    pub serial: u32,
+    /// The current value of every prop, by field index into `ServerClass::props`.
+    /// `None` if the prop has not been sent yet.
+    pub props: Vec<Option<PropValue>>,
+}
+
+impl Entity {
+    fn new(index: usize, class_id: usize, serial: u32, class: &ServerClass) -> Entity {
+        Entity {
+            index,
+            class_id,
+            serial,
+            props: vec![None; class.props.len()],
+        }
+    }
+
+    /// Look up a prop by its full path, like `m_iHealth`.
+    /// `class` must be the server class of this entity.
+    pub fn prop(&self, class: &ServerClass, path: &str) -> Option<&PropValue> {
+        let index = class.prop_index(path)?;
+        self.props.get(index)?.as_ref()
+    }
+
+    /// Read the list of changed field indices, and then the new values.
+    fn read_props(&mut self, data: &Cursor, class: &ServerClass) -> Result<()> {
+        let new_way = data.read_bit_bool()?;
+        let mut indices = vec![];
+        let mut last = None;
+        while let Some(index) = read_field_index(data, last, new_way)? {
+            indices.push(index);
+            last = Some(index);
+        }
+        for index in indices {
+            let prop = class
+                .props
+                .get(index)
+                .ok_or_else(|| DemoError::BadFieldIndex {
+                    index,
+                    class: class.name.clone(),
+                })?;
+            self.props[index] = Some(PropValue::decode(data, prop)?);
+        }
+        Ok(())
+    }
+}
+
+/// Reads the next changed field index, or `None` at the end of the list.
+/// Indices are sent as deltas from the last one.
+fn read_field_index(data: &Cursor, last: Option<usize>, new_way: bool) -> Result<Option<usize>> {
+    let next = last.map_or(0, |l| l + 1);
+    if new_way && data.read_bit_bool()? {
+        return Ok(Some(next));
+    }
+    let delta = if new_way && data.read_bit_bool()? {
+        data.read_ubits(3)?
+    } else {
+        let n = data.read_ubits(7)?;
+        match n & (32 | 64) {
+            32 => (n & !96) | (data.read_ubits(2)? << 5),
+            64 => (n & !96) | (data.read_ubits(4)? << 5),
+            96 => (n & !96) | (data.read_ubits(7)? << 5),
+            _ => n,
+        }
+    };
+    if delta == 0xFFF {
+        Ok(None)
+    } else {
+        Ok(Some(next + delta as usize))
+    }
+}
+
+/// Every entity currently in the PVS, by entity index.
+#[derive(Debug, Clone)]
+pub struct EntityTable {
+    entities: Vec<Option<Entity>>,
+    /// The raw instance baseline of each class, by class id.
+    /// New entities start from these, before their own props are applied.
+    baselines: HashMap<usize, Vec<u8>>,
+    /// `baselines`, decoded through the props of their class.
+    /// Decoded when first needed, since the data tables may not exist yet
+    /// when the baselines arrive.
+    decoded_baselines: HashMap<usize, Vec<Option<PropValue>>>,
+}
+
+impl Default for EntityTable {
+    fn default() -> Self {
+        EntityTable {
+            entities: vec![None; MAX_ENTITIES],
+            baselines: HashMap::new(),
+            decoded_baselines: HashMap::new(),
+        }
+    }
+}
+
+impl EntityTable {
+    pub fn new() -> EntityTable {
+        Self::default()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Entity> {
+        self.entities.get(index)?.as_ref()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.iter().flatten()
+    }
+
+    /// Set (or replace) the instance baseline of a class,
+    /// from an entry in the `instancebaseline` string table.
+    pub fn set_baseline(&mut self, class_id: usize, data: Vec<u8>) {
+        self.decoded_baselines.remove(&class_id);
+        self.baselines.insert(class_id, data);
+    }
+
+    /// The initial props of a new entity of the given class.
+    fn baseline(&mut self, class_id: usize, class: &ServerClass) -> Result<Vec<Option<PropValue>>> {
+        if let Some(props) = self.decoded_baselines.get(&class_id) {
+            return Ok(props.clone());
+        }
+        let mut entity = Entity::new(0, class_id, 0, class);
+        if let Some(data) = self.baselines.get(&class_id) {
+            entity.read_props(&Cursor::new(data), class)?;
+            self.decoded_baselines
+                .insert(class_id, entity.props.clone());
+        }
+        Ok(entity.props)
+    }
+
+    /// Apply the changes in a `CSVCMsg_PacketEntities` message.
+    /// A full update, one that isn't a delta, also removes every entity it leaves out.
+    pub fn apply(&mut self, msg: &CsvcMsgPacketEntities, data_table: &DataTable) -> Result<()> {
+        let data = Cursor::new(msg.entity_data());
+        let mut in_update = vec![false; MAX_ENTITIES];
+        let mut next = 0;
+        for _ in 0..msg.updated_entries() {
+            let index = next + data.read_ubit_var()? as usize;
+            next = index + 1;
+            if index >= MAX_ENTITIES {
+                return Err(DemoError::BadEntityIndex(index));
+            }
+            in_update[index] = true;
+
+            if !data.read_bit_bool()? {
+                if data.read_bit_bool()? {
+                    // Entering the PVS, so (re)create the entity.
+                    let class_id =
+                        data.read_ubits(data_table.service_class_bits as usize)? as usize;
+                    let serial = data.read_ubits(ENTITY_SERIAL_BITS)?;
+                    let class = server_class(data_table, class_id)?;
+                    let mut entity = Entity::new(index, class_id, serial, class);
+                    entity.props = self.baseline(class_id, class)?;
+                    entity.read_props(&data, class)?;
+                    self.entities[index] = Some(entity);
+                } else {
+                    // A delta against the entity we already have.
+                    let entity = self.entities[index]
+                        .as_mut()
+                        .ok_or(DemoError::UnknownEntity(index))?;
+                    let class = server_class(data_table, entity.class_id)?;
+                    entity.read_props(&data, class)?;
+                }
+            } else if data.read_bit_bool()? {
+                // Leaving the PVS, and being deleted.
+                self.entities[index] = None;
+            }
+        }
+        if !msg.is_delta() {
+            for (entity, in_update) in self.entities.iter_mut().zip(in_update) {
+                if !in_update {
+                    *entity = None;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn server_class(data_table: &DataTable, class_id: usize) -> Result<&ServerClass> {
+    data_table
+        .server_classes
+        .get(class_id)
+        .ok_or(DemoError::UnknownServerClass(class_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitbuffer::{BitWriteStream, LittleEndian};
+
+    use super::*;
+    use crate::data_tables::fixtures::player_table;
+
+    type Writer<'a> = BitWriteStream<'a, LittleEndian>;
+
+    fn bits(f: impl FnOnce(&mut Writer)) -> Vec<u8> {
+        let mut data = vec![];
+        let mut writer = BitWriteStream::new(&mut data, LittleEndian);
+        f(&mut writer);
+        data
+    }
+
+    fn packet(is_delta: bool, updated_entries: i32, entity_data: Vec<u8>) -> CsvcMsgPacketEntities {
+        CsvcMsgPacketEntities {
+            is_delta: Some(is_delta),
+            updated_entries: Some(updated_entries),
+            entity_data: Some(entity_data),
+            ..CsvcMsgPacketEntities::default()
+        }
+    }
+
+    /// The index delta of an entry, as a ubitvar small enough for 6 bits.
+    fn index_delta(w: &mut Writer, delta: u32) {
+        w.write_int(delta, 6).unwrap();
+    }
+
+    /// An entry for an entity entering the PVS, up to its props.
+    fn enter(w: &mut Writer, delta: u32, serial: u32) {
+        index_delta(w, delta);
+        w.write_bool(false).unwrap(); // not leaving
+        w.write_bool(true).unwrap(); // entering
+        w.write_int(0u32, 1).unwrap(); // class 0, of one
+        w.write_int(serial, ENTITY_SERIAL_BITS).unwrap();
+    }
+
+    /// A field index 1 to 8 past the next one, the new way.
+    fn small_delta(w: &mut Writer, delta: u32) {
+        w.write_bool(false).unwrap();
+        w.write_bool(true).unwrap();
+        w.write_int(delta, 3).unwrap();
+    }
+
+    /// The end of the field index list, the delta 0xFFF in the 7 bit form.
+    fn end_of_indices(w: &mut Writer, new_way: bool) {
+        if new_way {
+            w.write_bool(false).unwrap();
+            w.write_bool(false).unwrap();
+        }
+        w.write_int(0x1F | 96u32, 7).unwrap();
+        w.write_int(0x7Fu32, 7).unwrap();
+    }
+
+    fn int(table: &EntityTable, data_table: &DataTable, index: usize, path: &str) -> Option<i32> {
+        let class = &data_table.server_classes[0];
+        table.get(index)?.prop(class, path)?.as_int()
+    }
+
+    #[test]
+    fn enter_update_and_delete() {
+        let data_table = player_table();
+        let mut table = EntityTable::new();
+
+        let data = bits(|w| {
+            enter(w, 1, 5);
+            w.write_bool(true).unwrap(); // new way
+            small_delta(w, 4); // m_iHealth
+            w.write_bool(true).unwrap(); // the next one, m_local.m_nTickBase
+            end_of_indices(w, true);
+            w.write_int(100u32, 7).unwrap();
+            w.write_int(1234u32, 16).unwrap();
+
+            enter(w, 1, 6);
+            w.write_bool(false).unwrap(); // the old way, 7 bit deltas only
+            w.write_int(2u32, 7).unwrap(); // m_iTeamNum
+            end_of_indices(w, false);
+            w.write_int(3u32, 2).unwrap();
+        });
+        table.apply(&packet(true, 2, data), &data_table).unwrap();
+        assert_eq!(table.get(1).unwrap().serial, 5);
+        assert_eq!(int(&table, &data_table, 1, "m_iHealth"), Some(100));
+        assert_eq!(
+            int(&table, &data_table, 1, "m_local.m_nTickBase"),
+            Some(1234)
+        );
+        assert_eq!(int(&table, &data_table, 1, "m_iTeamNum"), None);
+        assert_eq!(int(&table, &data_table, 3, "m_iTeamNum"), Some(3));
+
+        let data = bits(|w| {
+            index_delta(w, 1);
+            w.write_bool(false).unwrap();
+            w.write_bool(false).unwrap(); // a delta
+            w.write_bool(true).unwrap();
+            small_delta(w, 2); // m_iTeamNum
+            end_of_indices(w, true);
+            w.write_int(2u32, 2).unwrap();
+
+            index_delta(w, 1);
+            w.write_bool(true).unwrap(); // leaving
+            w.write_bool(true).unwrap(); // and deleted
+        });
+        table.apply(&packet(true, 2, data), &data_table).unwrap();
+        assert_eq!(int(&table, &data_table, 1, "m_iTeamNum"), Some(2));
+        assert_eq!(int(&table, &data_table, 1, "m_iHealth"), Some(100));
+        assert!(table.get(3).is_none());
+
+        let data = bits(|w| {
+            index_delta(w, 1);
+            w.write_bool(true).unwrap(); // leaving
+            w.write_bool(false).unwrap(); // but not deleted, so it stays
+        });
+        table.apply(&packet(true, 1, data), &data_table).unwrap();
+        assert_eq!(int(&table, &data_table, 1, "m_iHealth"), Some(100));
+
+        // An update for an entity we never saw.
+        let data = bits(|w| {
+            index_delta(w, 7);
+            w.write_bool(false).unwrap();
+            w.write_bool(false).unwrap();
+        });
+        let err = table
+            .apply(&packet(true, 1, data), &data_table)
+            .unwrap_err();
+        assert!(matches!(err, DemoError::UnknownEntity(7)));
+    }
+
+    #[test]
+    fn full_update_removes_entities_left_out() {
+        let data_table = player_table();
+        let mut table = EntityTable::new();
+        let entity = |delta: u32, serial: u32| {
+            bits(|w| {
+                enter(w, delta, serial);
+                w.write_bool(true).unwrap();
+                end_of_indices(w, true);
+            })
+        };
+        table
+            .apply(&packet(true, 1, entity(1, 1)), &data_table)
+            .unwrap();
+        table
+            .apply(&packet(true, 1, entity(2, 2)), &data_table)
+            .unwrap();
+        assert_eq!(table.iter().count(), 2);
+
+        table
+            .apply(&packet(false, 1, entity(2, 3)), &data_table)
+            .unwrap();
+        assert!(table.get(1).is_none());
+        assert_eq!(table.get(2).unwrap().serial, 3);
+        assert_eq!(table.iter().count(), 1);
+    }
+
+    #[test]
+    fn new_entities_start_from_their_baseline() {
+        let data_table = player_table();
+        let mut table = EntityTable::new();
+        let baseline = |health: u32| {
+            bits(|w| {
+                w.write_bool(true).unwrap();
+                small_delta(w, 2); // m_iTeamNum
+                small_delta(w, 1); // m_iHealth
+                end_of_indices(w, true);
+                w.write_int(2u32, 2).unwrap();
+                w.write_int(health, 7).unwrap();
+            })
+        };
+        table.set_baseline(0, baseline(50));
+
+        // The entity only sends its health, over the baseline's.
+        let entity = |delta: u32, health: u32| {
+            bits(|w| {
+                enter(w, delta, 1);
+                w.write_bool(true).unwrap();
+                small_delta(w, 4);
+                end_of_indices(w, true);
+                w.write_int(health, 7).unwrap();
+            })
+        };
+        table
+            .apply(&packet(true, 1, entity(1, 90)), &data_table)
+            .unwrap();
+        assert_eq!(int(&table, &data_table, 1, "m_iTeamNum"), Some(2));
+        assert_eq!(int(&table, &data_table, 1, "m_iHealth"), Some(90));
+
+        // A new baseline replaces the cached decode of the old one.
+        table.set_baseline(0, baseline(70));
+        let data = bits(|w| {
+            enter(w, 2, 2);
+            w.write_bool(true).unwrap();
+            end_of_indices(w, true);
+        });
+        table.apply(&packet(true, 1, data), &data_table).unwrap();
+        assert_eq!(int(&table, &data_table, 2, "m_iHealth"), Some(70));
+        assert_eq!(int(&table, &data_table, 1, "m_iHealth"), Some(90));
+    }
+}
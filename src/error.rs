@@ -0,0 +1,109 @@
+//! The error type returned by every parser in this crate.
+
+use bitbuffer::BitError;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, DemoError>;
+
+#[derive(Debug, Error)]
+pub enum DemoError {
+    /// The data ended before a read could complete.
+    /// Usually means the demo is truncated.
+    #[error(
+        "Unexpected end of data at bit {bit_offset}: wanted {requested} bits, {remaining} left."
+    )]
+    UnexpectedEof {
+        bit_offset: usize,
+        requested: usize,
+        remaining: usize,
+    },
+    /// Any other failure from the underlying bit buffer, like bad UTF-8.
+    #[error("Bad data at bit {bit_offset}: {source}")]
+    BitBuffer {
+        bit_offset: usize,
+        #[source]
+        source: BitError,
+    },
+    /// The file does not start with `HL2DEMO\0`, so it is not a demo.
+    #[error("Not a demo file, found magic `{found:?}` at byte {byte_offset}.")]
+    BadMagic { found: Vec<u8>, byte_offset: usize },
+    #[error("Unknown demo command `{0}`.")]
+    UnknownCommand(u8),
+    #[error("Unknown message type `{0}`.")]
+    UnknownMessage(i32),
+    #[error("Unexpected message, only {expected} messages are allowed here.")]
+    UnexpectedMessage { expected: &'static str },
+    #[error("Could not decode protobuf message: {0}")]
+    ProtobufDecode(#[from] prost::DecodeError),
+    #[error("A variable sized int at bit {bit_offset} overflowed.")]
+    VarIntOverflow { bit_offset: usize },
+    #[error("Unknown string table id `{0}`.")]
+    UnknownStringTableId(i32),
+    /// A string table entry that should be a number, like a player slot.
+    #[error("Bad string table key `{0}`.")]
+    BadStringTableKey(String),
+    #[error("Invalid string table entry: {0}")]
+    InvalidStringTable(String),
+    #[error("Bad Steam id `{0}`.")]
+    BadSteamId(String),
+    /// A game event arrived before its descriptor in the game event list.
+    #[error("Unknown game event id `{0}`.")]
+    UnknownGameEvent(i32),
+    /// A game event with a different number of values than its descriptor has keys.
+    #[error("Game event `{name}` has {found} values, but its descriptor has {expected} keys.")]
+    GameEventKeyCount {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Bad game event key type `{0}`.")]
+    BadEventKeyType(i32),
+    #[error("Could not decompress string table data: {0}")]
+    Decompress(#[from] snap::Error),
+    /// Older servers compress string tables with LZSS instead of snappy.
+    #[error("String table data is compressed with LZSS, which is not supported.")]
+    LzssCompressed,
+    #[error("String table data decompressed to {found} bytes instead of {expected}.")]
+    DecompressedSize { expected: usize, found: usize },
+    #[error("Bad prop type `{0}`.")]
+    BadPropType(i32),
+    #[error("Bad prop flags `{0:#x}`.")]
+    BadPropFlags(i32),
+    #[error("Array prop `{0}` has no element prop.")]
+    MissingArrayElement(String),
+    #[error("No send table named `{0}`.")]
+    MissingSendTable(String),
+    #[error("Invalid server class id {class_id}, there are only {class_count} classes.")]
+    InvalidClassId { class_id: i16, class_count: i16 },
+    #[error("Unknown server class `{0}`.")]
+    UnknownServerClass(usize),
+    #[error("Entity index {0} is out of range.")]
+    BadEntityIndex(usize),
+    #[error("Entity {0} was updated before it was created.")]
+    UnknownEntity(usize),
+    #[error("Field index {index} is out of range for server class `{class}`.")]
+    BadFieldIndex { index: usize, class: String },
+    /// Entities can't be decoded until the data tables have been seen.
+    #[error("Got entity data before the data tables.")]
+    MissingDataTables,
+    /// A length prefix that is negative, or zero where that makes no sense.
+    #[error("Bad {what} length `{length}` at byte {byte_offset}.")]
+    BadLength {
+        what: &'static str,
+        length: i32,
+        byte_offset: usize,
+    },
+    #[error("Could not write CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[cfg(feature = "parquet")]
+    #[error("Could not build Arrow data: {0}")]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[cfg(feature = "parquet")]
+    #[error("Could not write Parquet: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[cfg(feature = "sqlite")]
+    #[error("Could not write to SQLite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
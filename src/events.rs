@@ -0,0 +1,304 @@
+//! Typed versions of the game events we care most about.
+//! Anything else is still available as a generic [`GameEvent`].
+//! Field names match the event keys in the game's `modevents.res`,
+//! and missing keys are read as zero, false, or an empty string.
+
+use serde::Serialize;
+
+use crate::game_events::GameEvent;
+
+/// A game event, and the tick of the frame it arrived in.
+#[derive(Debug, Clone, Serialize)]
+pub struct DemoEvent {
+    pub tick: u32,
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    PlayerDeath(PlayerDeath),
+    PlayerHurt(PlayerHurt),
+    WeaponFire(WeaponFire),
+    RoundStart(RoundStart),
+    RoundEnd(RoundEnd),
+    RoundFreezeEnd,
+    RoundOfficiallyEnded,
+    BombPlanted(BombEvent),
+    BombDefused(BombEvent),
+    BombExploded(BombEvent),
+    PlayerBlind(PlayerBlind),
+    HegrenadeDetonate(GrenadeDetonate),
+    FlashbangDetonate(GrenadeDetonate),
+    SmokegrenadeDetonate(GrenadeDetonate),
+    InfernoStartburn(InfernoStartburn),
+    ItemPurchase(ItemPurchase),
+    PlayerConnect(PlayerConnect),
+    PlayerDisconnect(PlayerDisconnect),
+    PlayerTeam(PlayerTeam),
+    /// Any event without a typed struct.
+    Other(GameEvent),
+}
+
+impl From<GameEvent> for Event {
+    fn from(event: GameEvent) -> Event {
+        let e = &event;
+        match event.name.as_str() {
+            "player_death" => Event::PlayerDeath(PlayerDeath {
+                userid: int(e, "userid"),
+                attacker: int(e, "attacker"),
+                assister: int(e, "assister"),
+                assistedflash: boolean(e, "assistedflash"),
+                weapon: string(e, "weapon"),
+                headshot: boolean(e, "headshot"),
+                penetrated: int(e, "penetrated"),
+                noscope: boolean(e, "noscope"),
+                thrusmoke: boolean(e, "thrusmoke"),
+                attackerblind: boolean(e, "attackerblind"),
+                distance: float(e, "distance"),
+            }),
+            "player_hurt" => Event::PlayerHurt(PlayerHurt {
+                userid: int(e, "userid"),
+                attacker: int(e, "attacker"),
+                health: int(e, "health"),
+                armor: int(e, "armor"),
+                weapon: string(e, "weapon"),
+                dmg_health: int(e, "dmg_health"),
+                dmg_armor: int(e, "dmg_armor"),
+                hitgroup: int(e, "hitgroup"),
+            }),
+            "weapon_fire" => Event::WeaponFire(WeaponFire {
+                userid: int(e, "userid"),
+                weapon: string(e, "weapon"),
+                silenced: boolean(e, "silenced"),
+            }),
+            "round_start" => Event::RoundStart(RoundStart {
+                timelimit: int(e, "timelimit"),
+                fraglimit: int(e, "fraglimit"),
+                objective: string(e, "objective"),
+            }),
+            "round_end" => Event::RoundEnd(RoundEnd {
+                winner: int(e, "winner"),
+                reason: int(e, "reason"),
+                message: string(e, "message"),
+                player_count: int(e, "player_count"),
+            }),
+            "round_freeze_end" => Event::RoundFreezeEnd,
+            "round_officially_ended" => Event::RoundOfficiallyEnded,
+            "bomb_planted" => Event::BombPlanted(BombEvent::new(e)),
+            "bomb_defused" => Event::BombDefused(BombEvent::new(e)),
+            "bomb_exploded" => Event::BombExploded(BombEvent::new(e)),
+            "player_blind" => Event::PlayerBlind(PlayerBlind {
+                userid: int(e, "userid"),
+                attacker: int(e, "attacker"),
+                entityid: int(e, "entityid"),
+                blind_duration: float(e, "blind_duration"),
+            }),
+            "hegrenade_detonate" => Event::HegrenadeDetonate(GrenadeDetonate::new(e)),
+            "flashbang_detonate" => Event::FlashbangDetonate(GrenadeDetonate::new(e)),
+            "smokegrenade_detonate" => Event::SmokegrenadeDetonate(GrenadeDetonate::new(e)),
+            "inferno_startburn" => Event::InfernoStartburn(InfernoStartburn {
+                entityid: int(e, "entityid"),
+                x: float(e, "x"),
+                y: float(e, "y"),
+                z: float(e, "z"),
+            }),
+            "item_purchase" => Event::ItemPurchase(ItemPurchase {
+                userid: int(e, "userid"),
+                team: int(e, "team"),
+                weapon: string(e, "weapon"),
+            }),
+            "player_connect" => Event::PlayerConnect(PlayerConnect {
+                name: string(e, "name"),
+                index: int(e, "index"),
+                userid: int(e, "userid"),
+                networkid: string(e, "networkid"),
+            }),
+            "player_disconnect" => Event::PlayerDisconnect(PlayerDisconnect {
+                userid: int(e, "userid"),
+                reason: string(e, "reason"),
+                name: string(e, "name"),
+                networkid: string(e, "networkid"),
+            }),
+            "player_team" => Event::PlayerTeam(PlayerTeam {
+                userid: int(e, "userid"),
+                team: int(e, "team"),
+                oldteam: int(e, "oldteam"),
+                disconnect: boolean(e, "disconnect"),
+                isbot: boolean(e, "isbot"),
+                silent: boolean(e, "silent"),
+            }),
+            _ => Event::Other(event),
+        }
+    }
+}
+
+fn int(event: &GameEvent, key: &str) -> i32 {
+    event.get(key).and_then(|v| v.as_int()).unwrap_or_default()
+}
+
+fn float(event: &GameEvent, key: &str) -> f32 {
+    event
+        .get(key)
+        .and_then(|v| v.as_float())
+        .unwrap_or_default()
+}
+
+fn boolean(event: &GameEvent, key: &str) -> bool {
+    event.get(key).and_then(|v| v.as_bool()).unwrap_or_default()
+}
+
+fn string(event: &GameEvent, key: &str) -> String {
+    event
+        .get(key)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// User ids (`userid`, `attacker`, ...) are 0 when there is no such player,
+/// like the attacker of a fall damage death.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerDeath {
+    pub userid: i32,
+    pub attacker: i32,
+    pub assister: i32,
+    pub assistedflash: bool,
+    pub weapon: String,
+    pub headshot: bool,
+    /// The number of objects the bullet went through.
+    pub penetrated: i32,
+    pub noscope: bool,
+    pub thrusmoke: bool,
+    pub attackerblind: bool,
+    pub distance: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerHurt {
+    pub userid: i32,
+    pub attacker: i32,
+    /// Health left after the damage.
+    pub health: i32,
+    /// Armor left after the damage.
+    pub armor: i32,
+    pub weapon: String,
+    pub dmg_health: i32,
+    pub dmg_armor: i32,
+    pub hitgroup: i32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WeaponFire {
+    pub userid: i32,
+    pub weapon: String,
+    pub silenced: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RoundStart {
+    pub timelimit: i32,
+    pub fraglimit: i32,
+    pub objective: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RoundEnd {
+    /// The winning team, 2 for T and 3 for CT.
+    pub winner: i32,
+    /// The engine's `CSRoundEndReason`.
+    pub reason: i32,
+    pub message: String,
+    pub player_count: i32,
+}
+
+/// Shared by `bomb_planted`, `bomb_defused`, and `bomb_exploded`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BombEvent {
+    pub userid: i32,
+    /// The entity index of the bomb site.
+    pub site: i32,
+}
+
+impl BombEvent {
+    fn new(e: &GameEvent) -> BombEvent {
+        BombEvent {
+            userid: int(e, "userid"),
+            site: int(e, "site"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerBlind {
+    pub userid: i32,
+    pub attacker: i32,
+    /// The entity index of the flashbang.
+    pub entityid: i32,
+    pub blind_duration: f32,
+}
+
+/// Shared by the HE, flashbang, and smoke grenade detonation events.
+#[derive(Debug, Clone, Serialize)]
+pub struct GrenadeDetonate {
+    pub userid: i32,
+    pub entityid: i32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl GrenadeDetonate {
+    fn new(e: &GameEvent) -> GrenadeDetonate {
+        GrenadeDetonate {
+            userid: int(e, "userid"),
+            entityid: int(e, "entityid"),
+            x: float(e, "x"),
+            y: float(e, "y"),
+            z: float(e, "z"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InfernoStartburn {
+    pub entityid: i32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemPurchase {
+    pub userid: i32,
+    pub team: i32,
+    pub weapon: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerConnect {
+    pub name: String,
+    /// The player's entity slot, minus one.
+    pub index: i32,
+    pub userid: i32,
+    /// `STEAM_1:...`, or `BOT`.
+    pub networkid: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerDisconnect {
+    pub userid: i32,
+    pub reason: String,
+    pub name: String,
+    pub networkid: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerTeam {
+    pub userid: i32,
+    pub team: i32,
+    pub oldteam: i32,
+    pub disconnect: bool,
+    pub isbot: bool,
+    pub silent: bool,
+}
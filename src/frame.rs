@@ -2,6 +2,7 @@ use serde::Serialize;
 
 use crate::cursor::Cursor;
 use crate::data_tables::DataTable;
+use crate::error::{DemoError, Result};
 use crate::packet::Packet;
 use crate::string_tables::StringTables;
 
@@ -19,7 +20,7 @@ pub enum Command {
 }
 
 impl Command {
-    pub fn parse(which: u8, data: &Cursor) -> anyhow::Result<Command> {
+    pub fn parse(which: u8, data: &Cursor) -> Result<Command> {
         Ok(match which {
             1 => {
                 let packet = Packet::parse(data)?;
@@ -31,16 +32,15 @@ impl Command {
             }
             3 => Command::SyncTick,
             4 => {
-                let length = data.read_i32()?;
-                assert!(length > 0);
-                let chunk = data.read_bytes(length as usize)?.into_owned();
+                let length = data.read_length("console command", false)?;
+                let chunk = data.read_bytes(length)?.into_owned();
                 Command::ConsoleCmd(chunk)
             }
             5 => {
                 // Unused by any parser.
                 let _outgoing_sequence = data.read_i32()?;
-                let length = data.read_i32()?;
-                let chunk = data.read_bytes(length as usize)?.into_owned();
+                let length = data.read_length("user command", true)?;
+                let chunk = data.read_bytes(length)?.into_owned();
                 Command::UserCmd(chunk)
             }
             6 => {
@@ -50,8 +50,8 @@ impl Command {
 
                 // //And now we have the entities, we can bind events on them.
                 // BindEntites();
-                let length = data.read_i32()?;
-                let chunk = data.chunk_bytes(length as usize)?;
+                let length = data.read_length("data tables", true)?;
+                let chunk = data.chunk_bytes(length)?;
                 let table = DataTable::parse(&chunk)?;
                 Command::DataTables(table)
             }
@@ -59,27 +59,27 @@ impl Command {
             8 => Command::CustomData,
             9 => {
                 // StringTables
-                let length = data.read_i32()?;
-                let chunk = data.chunk_bytes(length as usize)?;
+                let length = data.read_length("string tables", true)?;
+                let chunk = data.chunk_bytes(length)?;
                 let table = StringTables::parse(&chunk)?;
                 Command::StringTables(table)
             }
-            n => anyhow::bail!("Not sure how to suport command: {n}"),
+            n => return Err(DemoError::UnknownCommand(n)),
         })
     }
 }
 
 #[derive(Debug, Serialize)]
 pub struct Frame {
-    command: Command,
-    tick_number: u32,
+    pub command: Command,
+    pub tick_number: u32,
     /// Have not seen this ever be nonzero.
     #[serde(skip)]
     _playerslot: i8,
 }
 
 impl Frame {
-    pub fn parse(data: &Cursor) -> anyhow::Result<Frame> {
+    pub fn parse(data: &Cursor) -> Result<Frame> {
         let which_command = data.read_u8()?;
         let tick_number = data.read_u32()?;
         let _playerslot = data.read_i8()?;
@@ -93,7 +93,7 @@ impl Frame {
         })
     }
 
-    pub(crate) fn is_last(&self) -> bool {
+    pub fn is_last(&self) -> bool {
         matches!(self.command, Command::Stop)
     }
 }
@@ -0,0 +1,252 @@
+//! Decoding of game events, like `player_death`.
+//! `CSVCMsg_GameEvent` only carries an event id and a list of values,
+//! the names for both come from the descriptors in `CSVCMsg_GameEventList`.
+
+use std::collections::HashMap;
+
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::error::{DemoError, Result};
+use crate::protos::csvc_msg_game_event::KeyT;
+use crate::protos::{CsvcMsgGameEvent, CsvcMsgGameEventList};
+
+/// The description of one kind of event.
+#[derive(Debug, Clone, Serialize)]
+pub struct GameEventDescriptor {
+    pub id: i32,
+    pub name: String,
+    /// The name of each key, in the order the values are sent.
+    pub keys: Vec<String>,
+}
+
+/// Every kind of event the server told us about, by event id.
+#[derive(Debug, Clone, Default)]
+pub struct GameEventRegistry {
+    descriptors: HashMap<i32, GameEventDescriptor>,
+}
+
+impl GameEventRegistry {
+    pub fn new(list: &CsvcMsgGameEventList) -> GameEventRegistry {
+        let descriptors = list
+            .descriptors
+            .iter()
+            .map(|d| {
+                let descriptor = GameEventDescriptor {
+                    id: d.eventid(),
+                    name: d.name().to_string(),
+                    keys: d.keys.iter().map(|k| k.name().to_string()).collect(),
+                };
+                (descriptor.id, descriptor)
+            })
+            .collect();
+        GameEventRegistry { descriptors }
+    }
+
+    pub fn get(&self, id: i32) -> Option<&GameEventDescriptor> {
+        self.descriptors.get(&id)
+    }
+
+    /// Give the values of a raw event their names.
+    pub fn decode(&self, event: &CsvcMsgGameEvent) -> Result<GameEvent> {
+        let descriptor = self
+            .get(event.eventid())
+            .ok_or(DemoError::UnknownGameEvent(event.eventid()))?;
+        if event.keys.len() != descriptor.keys.len() {
+            return Err(DemoError::GameEventKeyCount {
+                name: descriptor.name.clone(),
+                expected: descriptor.keys.len(),
+                found: event.keys.len(),
+            });
+        }
+        let mut fields = IndexMap::with_capacity(event.keys.len());
+        for (name, key) in descriptor.keys.iter().zip(&event.keys) {
+            fields.insert(name.clone(), EventValue::from_key(key)?);
+        }
+        Ok(GameEvent {
+            name: descriptor.name.clone(),
+            fields,
+        })
+    }
+}
+
+/// A game event, with named fields.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GameEvent {
+    pub name: String,
+    /// The fields, in the order the server sent them.
+    pub fields: IndexMap<String, EventValue>,
+}
+
+impl GameEvent {
+    pub fn get(&self, key: &str) -> Option<&EventValue> {
+        self.fields.get(key)
+    }
+}
+
+/// A single value in a game event.
+/// Serialized as just the value, without the type.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum EventValue {
+    String(String),
+    Float(f32),
+    Long(i32),
+    Short(i16),
+    Byte(u8),
+    Bool(bool),
+    Uint64(u64),
+    WString(String),
+}
+
+impl EventValue {
+    fn from_key(key: &KeyT) -> Result<EventValue> {
+        Ok(match key.r#type() {
+            1 => EventValue::String(key.val_string().to_string()),
+            2 => EventValue::Float(key.val_float()),
+            3 => EventValue::Long(key.val_long()),
+            4 => EventValue::Short(key.val_short() as i16),
+            5 => EventValue::Byte(key.val_byte() as u8),
+            6 => EventValue::Bool(key.val_bool()),
+            7 => EventValue::Uint64(key.val_uint64()),
+            8 => EventValue::WString(String::from_utf8_lossy(key.val_wstring()).into_owned()),
+            n => return Err(DemoError::BadEventKeyType(n)),
+        })
+    }
+
+    /// Any of the integer types, widened to an i32.
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            EventValue::Long(n) => Some(*n),
+            EventValue::Short(n) => Some(*n as i32),
+            EventValue::Byte(n) => Some(*n as i32),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f32> {
+        match self {
+            EventValue::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// Bools, and integers that are used as bools.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            EventValue::Bool(b) => Some(*b),
+            other => other.as_int().map(|n| n != 0),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            EventValue::String(s) | EventValue::WString(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            EventValue::Uint64(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::csvc_msg_game_event_list::{DescriptorT, KeyT as DescriptorKey};
+
+    fn registry() -> GameEventRegistry {
+        let key = |name: &str, kind: i32| DescriptorKey {
+            r#type: Some(kind),
+            name: Some(name.to_string()),
+        };
+        GameEventRegistry::new(&CsvcMsgGameEventList {
+            descriptors: vec![DescriptorT {
+                eventid: Some(23),
+                name: Some("player_death".to_string()),
+                keys: vec![key("userid", 4), key("weapon", 1), key("headshot", 6)],
+            }],
+        })
+    }
+
+    fn event(keys: Vec<KeyT>) -> CsvcMsgGameEvent {
+        CsvcMsgGameEvent {
+            eventid: Some(23),
+            keys,
+            ..CsvcMsgGameEvent::default()
+        }
+    }
+
+    fn short(n: i32) -> KeyT {
+        KeyT {
+            r#type: Some(4),
+            val_short: Some(n),
+            ..KeyT::default()
+        }
+    }
+
+    fn string(s: &str) -> KeyT {
+        KeyT {
+            r#type: Some(1),
+            val_string: Some(s.to_string()),
+            ..KeyT::default()
+        }
+    }
+
+    fn bool(b: bool) -> KeyT {
+        KeyT {
+            r#type: Some(6),
+            val_bool: Some(b),
+            ..KeyT::default()
+        }
+    }
+
+    #[test]
+    fn decode() {
+        let event = event(vec![short(-3), string("ak47"), bool(true)]);
+        let decoded = registry().decode(&event).unwrap();
+        assert_eq!(decoded.name, "player_death");
+        let names: Vec<_> = decoded.fields.keys().map(String::as_str).collect();
+        assert_eq!(names, ["userid", "weapon", "headshot"]);
+        assert_eq!(decoded.get("userid"), Some(&EventValue::Short(-3)));
+        assert_eq!(
+            decoded.get("weapon").and_then(EventValue::as_str),
+            Some("ak47")
+        );
+        assert_eq!(
+            decoded.get("headshot").and_then(EventValue::as_bool),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let registry = registry();
+        let short_event = event(vec![short(3), string("ak47")]);
+        assert!(matches!(
+            registry.decode(&short_event),
+            Err(DemoError::GameEventKeyCount {
+                expected: 3,
+                found: 2,
+                ..
+            })
+        ));
+
+        let mut unknown = event(vec![]);
+        unknown.eventid = Some(24);
+        assert!(matches!(
+            registry.decode(&unknown),
+            Err(DemoError::UnknownGameEvent(24))
+        ));
+
+        let bad_type = event(vec![short(3), string("ak47"), KeyT::default()]);
+        assert!(matches!(
+            registry.decode(&bad_type),
+            Err(DemoError::BadEventKeyType(_))
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+//! Tracks the state of the game as frames are parsed.
+//! Feed it every frame of a demo, in order.
+
+use crate::data_tables::DataTable;
+use crate::entities::EntityTable;
+use crate::error::{DemoError, Result};
+use crate::events::DemoEvent;
+use crate::frame::{Command, Frame};
+use crate::game_events::{GameEvent, GameEventRegistry};
+use crate::message::Message;
+use crate::roster::Roster;
+use crate::string_tables::{PlayerInfo, StringTable, StringTableChange, StringTableManager};
+
+#[derive(Debug, Default)]
+pub struct GameState {
+    /// The data tables, once the `DataTables` command has been seen.
+    pub data_table: Option<DataTable>,
+    pub entities: EntityTable,
+    /// The game event descriptors, once the game event list has been seen.
+    pub game_events: Option<GameEventRegistry>,
+    pub string_tables: StringTableManager,
+    pub roster: Roster,
+    /// Seconds per tick, once the server info has been seen.
+    pub tick_interval: Option<f32>,
+}
+
+impl GameState {
+    pub fn new() -> GameState {
+        Self::default()
+    }
+
+    /// Update the state with a frame, returning the game events in it.
+    pub fn handle_frame(&mut self, frame: &Frame) -> Result<Vec<DemoEvent>> {
+        let mut events = vec![];
+        match &frame.command {
+            Command::SignOn(packet) | Command::Packet(packet) => {
+                for msg in &packet.messages {
+                    if let Some(event) = self.handle_message(msg, frame.tick_number)? {
+                        let event = DemoEvent {
+                            tick: frame.tick_number,
+                            event: event.into(),
+                        };
+                        self.roster.handle_event(&event);
+                        events.push(event);
+                    }
+                }
+            }
+            Command::DataTables(table) => self.data_table = Some(table.clone()),
+            Command::StringTables(tables) => {
+                for table in &tables.tables {
+                    match table {
+                        StringTable::InstanceBaseline(baselines) => {
+                            for (&class_id, data) in baselines {
+                                self.entities.set_baseline(class_id as usize, data.clone());
+                            }
+                        }
+                        StringTable::UserInfo(players) => {
+                            for (&slot, info) in players {
+                                self.roster
+                                    .update(slot as usize, Some(info), frame.tick_number);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(events)
+    }
+
+    fn handle_message(&mut self, msg: &Message, tick: u32) -> Result<Option<GameEvent>> {
+        match msg {
+            Message::PacketEntities(entities) => {
+                let data_table = self
+                    .data_table
+                    .as_ref()
+                    .ok_or(DemoError::MissingDataTables)?;
+                self.entities.apply(entities, data_table)?;
+            }
+            Message::CreateStringTable(create) => {
+                let change = self.string_tables.create(create)?;
+                self.handle_string_table_change(change, tick)?;
+            }
+            Message::UpdateStringTable(update) => {
+                let change = self.string_tables.update(update)?;
+                self.handle_string_table_change(change, tick)?;
+            }
+            Message::ServerInfo(info) => self.tick_interval = info.tick_interval,
+            Message::GameEventList(list) => self.game_events = Some(GameEventRegistry::new(list)),
+            Message::GameEvent(event) => {
+                let registry = self
+                    .game_events
+                    .as_ref()
+                    .ok_or(DemoError::UnknownGameEvent(event.eventid()))?;
+                return Ok(Some(registry.decode(event)?));
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Keep the entity baselines up to date with the `instancebaseline` table,
+    /// and the roster with the `userinfo` table.
+    fn handle_string_table_change(&mut self, change: StringTableChange, tick: u32) -> Result<()> {
+        let Some(table) = self.string_tables.by_id(change.table_id) else {
+            return Ok(());
+        };
+        if table.info.name == "userinfo" {
+            for slot in change.changed {
+                let info = match &table.entries[slot].user_data {
+                    Some(data) if !data.is_empty() => Some(PlayerInfo::from_user_data(data)?),
+                    _ => None,
+                };
+                self.roster.update(slot, info.as_ref(), tick);
+            }
+            return Ok(());
+        }
+        if table.info.name != "instancebaseline" {
+            return Ok(());
+        }
+        for index in change.changed {
+            let entry = &table.entries[index];
+            let class_id = entry
+                .string
+                .parse()
+                .map_err(|_| DemoError::BadStringTableKey(entry.string.clone()))?;
+            if let Some(data) = &entry.user_data {
+                self.entities.set_baseline(class_id, data.clone());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Kills with everything around them: who was involved, where they were,
+//! and where they were looking.
+
+use serde::Serialize;
+
+use crate::events::PlayerDeath;
+use crate::game_state::GameState;
+use crate::positions::PlayerPosition;
+use crate::steam_id::SteamId;
+
+/// A `player_death` event, with the players resolved through the roster
+/// and their positions and view angles taken from their entities.
+/// Positions are `None` when there is no player entity, like for a world kill.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Kill {
+    pub tick: u32,
+    pub round: u32,
+    pub victim_user_id: Option<i32>,
+    pub victim_name: String,
+    pub victim_steam_id: Option<SteamId>,
+    pub victim_team: Option<i32>,
+    pub attacker_user_id: Option<i32>,
+    pub attacker_name: String,
+    pub attacker_steam_id: Option<SteamId>,
+    pub attacker_team: Option<i32>,
+    pub assister_user_id: Option<i32>,
+    pub assister_name: String,
+    pub assister_steam_id: Option<SteamId>,
+    pub weapon: String,
+    pub headshot: bool,
+    pub penetrated: i32,
+    pub noscope: bool,
+    pub thrusmoke: bool,
+    pub attackerblind: bool,
+    /// The assister only helped by flashing the victim.
+    pub assistedflash: bool,
+    /// From the event, or between the two players if the event doesn't say.
+    pub distance: f32,
+    pub victim_x: Option<f32>,
+    pub victim_y: Option<f32>,
+    pub victim_z: Option<f32>,
+    pub victim_pitch: Option<f32>,
+    pub victim_yaw: Option<f32>,
+    pub attacker_x: Option<f32>,
+    pub attacker_y: Option<f32>,
+    pub attacker_z: Option<f32>,
+    pub attacker_pitch: Option<f32>,
+    pub attacker_yaw: Option<f32>,
+}
+
+impl Kill {
+    pub fn new(death: &PlayerDeath, state: &GameState, tick: u32, round: u32) -> Kill {
+        let (victim_user_id, victim_name, victim_steam_id) = who(state, death.userid);
+        let (attacker_user_id, attacker_name, attacker_steam_id) = who(state, death.attacker);
+        let (assister_user_id, assister_name, assister_steam_id) = who(state, death.assister);
+        let victim = position(state, death.userid, tick);
+        let attacker = position(state, death.attacker, tick);
+
+        // The entity knows best, but the roster knows teams from `player_team` events too.
+        let team = |user_id: i32, position: &Option<PlayerPosition>| {
+            position.as_ref().map(|p| p.team).or_else(|| {
+                let player = state.roster.by_user_id(user_id)?;
+                Some(player.team).filter(|&team| team != 0)
+            })
+        };
+
+        let distance = match (&victim, &attacker) {
+            (Some(v), Some(a)) if death.distance == 0.0 => {
+                ((v.x - a.x).powi(2) + (v.y - a.y).powi(2) + (v.z - a.z).powi(2)).sqrt()
+            }
+            _ => death.distance,
+        };
+
+        Kill {
+            tick,
+            round,
+            victim_user_id,
+            victim_name,
+            victim_steam_id,
+            victim_team: team(death.userid, &victim),
+            attacker_user_id,
+            attacker_name,
+            attacker_steam_id,
+            attacker_team: team(death.attacker, &attacker),
+            assister_user_id,
+            assister_name,
+            assister_steam_id,
+            weapon: death.weapon.clone(),
+            headshot: death.headshot,
+            penetrated: death.penetrated,
+            noscope: death.noscope,
+            thrusmoke: death.thrusmoke,
+            attackerblind: death.attackerblind,
+            assistedflash: death.assistedflash,
+            distance,
+            victim_x: victim.as_ref().map(|p| p.x),
+            victim_y: victim.as_ref().map(|p| p.y),
+            victim_z: victim.as_ref().map(|p| p.z),
+            victim_pitch: victim.as_ref().map(|p| p.pitch),
+            victim_yaw: victim.as_ref().map(|p| p.yaw),
+            attacker_x: attacker.as_ref().map(|p| p.x),
+            attacker_y: attacker.as_ref().map(|p| p.y),
+            attacker_z: attacker.as_ref().map(|p| p.z),
+            attacker_pitch: attacker.as_ref().map(|p| p.pitch),
+            attacker_yaw: attacker.as_ref().map(|p| p.yaw),
+        }
+    }
+
+    /// The attacker killed a teammate. Suicides don't count.
+    pub fn is_team_kill(&self) -> bool {
+        self.attacker_user_id != self.victim_user_id
+            && self.attacker_team.is_some()
+            && self.attacker_team == self.victim_team
+    }
+}
+
+/// The `user_id`, `name` and `steam_id` columns for a user id from an event.
+/// A user id of zero means there was no such player.
+pub(crate) fn who(state: &GameState, user_id: i32) -> (Option<i32>, String, Option<SteamId>) {
+    if user_id == 0 {
+        return (None, String::new(), None);
+    }
+    match state.roster.by_user_id(user_id) {
+        Some(player) => (Some(user_id), player.name.clone(), player.steam_id),
+        None => (Some(user_id), String::new(), None),
+    }
+}
+
+fn position(state: &GameState, user_id: i32, tick: u32) -> Option<PlayerPosition> {
+    let player = state.roster.by_user_id(user_id)?;
+    PlayerPosition::of(state, player.entity_index(), tick)
+}
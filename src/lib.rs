@@ -0,0 +1,68 @@
+//! A parser for CSGO .dem files
+//! You can find demos from your own matches,
+//! Or download them from some online source
+//! Try https://www.hltv.org/matches/2359846/outsiders-vs-heroic-iem-rio-major-2022
+//! in the 'rewatch' tab there is a GOTV demo link.
+//!
+//! The usual entry point is [`Demo::parse`], which takes a [`Cursor`] over the
+//! raw bytes of a `.dem` file. To avoid holding every frame in memory at once,
+//! use [`DemoReader`] and iterate over its frames instead.
+//! [`DemoStream`] does the same for any `std::io::Read`.
+//!
+//! Frames only carry what changed since the last one. To follow the state of
+//! the game (like the entities in it), feed every frame to a [`GameState`].
+
+#[cfg(feature = "parquet")]
+pub mod columnar;
+pub mod cursor;
+pub mod data_tables;
+pub mod demo;
+pub mod economy;
+pub mod entities;
+pub mod error;
+pub mod events;
+pub mod frame;
+pub mod game_events;
+pub mod game_state;
+pub mod kills;
+pub mod message;
+pub mod packet;
+pub mod positions;
+pub mod props;
+pub mod roster;
+pub mod rounds;
+pub mod scoreboard;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod steam_id;
+pub mod stream;
+pub mod string_tables;
+pub mod tables;
+/// The Valve protobuf definitions, generated by `prost` at build time.
+#[allow(clippy::all)]
+pub mod protos {
+    include!(concat!(env!("OUT_DIR"), "/_.rs"));
+}
+
+pub use cursor::Cursor;
+pub use data_tables::{DataTable, ServerClass};
+pub use demo::{Demo, DemoHeader, DemoReader, FrameIter};
+pub use economy::{BuyType, EconomyRow, EconomyTracker, PlayerEconomyRow};
+pub use entities::{Entity, EntityTable};
+pub use error::DemoError;
+pub use events::{DemoEvent, Event};
+pub use frame::{Command, Frame};
+pub use game_events::{EventValue, GameEvent, GameEventRegistry};
+pub use game_state::GameState;
+pub use kills::Kill;
+pub use message::Message;
+pub use packet::Packet;
+pub use positions::{PlayerPosition, PositionSampler};
+pub use props::PropValue;
+pub use roster::{Player, Roster};
+pub use rounds::{Round, RoundOptions, RoundTracker};
+pub use scoreboard::{ScoreboardBuilder, ScoreboardRow};
+pub use steam_id::SteamId;
+pub use stream::DemoStream;
+pub use string_tables::{StringTableManager, StringTables};
+pub use tables::{TableBuilder, Tables};
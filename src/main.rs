@@ -1,42 +1,77 @@
-//! A parser for CSGO .dem files
-//! You can find demos from your own matches,
-//! Or download them from some online source
-//! Try https://www.hltv.org/matches/2359846/outsiders-vs-heroic-iem-rio-major-2022
-//! in the 'rewatch' tab there is a GOTV demo link.
-
-pub mod cursor;
-mod data_tables;
-mod demo;
-mod frame;
-pub mod message;
-mod packet;
-mod string_tables;
-mod protos {
-    include!(concat!(env!("OUT_DIR"), "/_.rs"));
-}
+//! The command line interface to the `csgo_demo_parser` library.
+//! Parses a single .dem file and dumps it as JSON (or NDJSON),
+//! or writes tables of what happened in it as CSV (or Parquet, or SQLite).
 
 use std::fs::File;
-use std::io::{BufReader, Read, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
 
-use clap::Parser;
-use cursor::Cursor;
-use demo::Demo;
+use clap::{Parser, ValueEnum};
+use csgo_demo_parser::{Cursor, Demo, DemoStream, Frame, RoundOptions, Tables};
+use serde::Serialize;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// The output file name.
+    /// The output file name, or directory name for CSV and Parquet.
+    /// Defaults to `OUT.json`, `OUT.ndjson`, `OUT.sqlite`, or `OUT` for a directory.
     /// If the file exists, it will be overwritten,
     /// if the file does not exist, it will be created.
-    #[arg(short, long, default_value = "OUT.json")]
-    output: std::path::PathBuf,
+    #[arg(short, long)]
+    output: Option<PathBuf>,
 
     /// The input .dem file to operate on.
-    input: std::path::PathBuf,
+    input: PathBuf,
 
     /// Minify the contents, if unset, the output JSON is 'prettified'
     #[arg(short, long, default_value_t = false)]
     minified: bool,
+
+    /// What to write.
+    #[arg(short, long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+
+    /// Take a row of player positions every this many ticks.
+    #[arg(long, default_value_t = 16)]
+    sample_rate: u32,
+
+    /// Leave warmup rounds out of the tables.
+    #[arg(long, default_value_t = false)]
+    exclude_warmup: bool,
+
+    /// Leave knife rounds out of the tables.
+    #[arg(long, default_value_t = false)]
+    exclude_knife: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    /// A JSON dump of every frame.
+    Json,
+    /// The header, and then every frame, as one JSON object per line.
+    /// Frames are written as they are parsed, so any size of demo fits.
+    Ndjson,
+    /// A directory of CSV files: kills, damage, rounds, players, positions, grenades,
+    /// purchases, the scoreboard, and the economy of each team and player.
+    Csv,
+    /// The scoreboard as JSON, a row of stats per player.
+    Scoreboard,
+    /// The same tables as CSV, as a directory of Parquet files.
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// The same tables as CSV, added to a SQLite database.
+    /// Writing a demo that is already in the database replaces it.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+/// A line of NDJSON output.
+#[derive(Serialize)]
+struct FrameLine<'a> {
+    /// The number of frames before this one.
+    index: usize,
+    #[serde(flatten)]
+    frame: &'a Frame,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -44,21 +79,78 @@ fn main() -> anyhow::Result<()> {
     let fname = args.input;
     let f = File::open(fname)?;
     let mut buf = BufReader::new(f);
-    let mut raw = Vec::new();
-    buf.read_to_end(&mut raw)?;
+    let round_options = RoundOptions {
+        exclude_warmup: args.exclude_warmup,
+        exclude_knife: args.exclude_knife,
+    };
 
-    let cursor = Cursor::new(&raw);
-    let demo = Demo::parse(&cursor)?;
+    match args.format {
+        Format::Json => {
+            let mut raw = Vec::new();
+            buf.read_to_end(&mut raw)?;
 
-    let json = if !args.minified {
-        serde_json::to_string_pretty(&demo)?
-    } else {
-        serde_json::to_string(&demo)?
-    };
+            let cursor = Cursor::new(&raw);
+            let demo = Demo::parse(&cursor)?;
+
+            let json = if !args.minified {
+                serde_json::to_string_pretty(&demo)?
+            } else {
+                serde_json::to_string(&demo)?
+            };
+
+            let output = args.output.unwrap_or_else(|| "OUT.json".into());
+            let mut output = File::create(output)?;
+            output.write_all(json.as_bytes())?;
+        }
+        Format::Ndjson => {
+            let stream = DemoStream::new(buf)?;
+            let output = args.output.unwrap_or_else(|| "OUT.ndjson".into());
+            let mut output = BufWriter::new(File::create(output)?);
+            serde_json::to_writer(&mut output, &stream.header)?;
+            output.write_all(b"\n")?;
+            for (index, frame) in stream.enumerate() {
+                let frame = &frame?;
+                serde_json::to_writer(&mut output, &FrameLine { index, frame })?;
+                output.write_all(b"\n")?;
+            }
+            output.flush()?;
+        }
+        Format::Csv => {
+            let stream = DemoStream::new(buf)?;
+            let tables = Tables::from_frames(stream, args.sample_rate, round_options)?;
+            tables.write_csv(&args.output.unwrap_or_else(|| "OUT".into()))?;
+        }
+        Format::Scoreboard => {
+            let stream = DemoStream::new(buf)?;
+            let tables = Tables::from_frames(stream, args.sample_rate, round_options)?;
+            let json = if !args.minified {
+                serde_json::to_string_pretty(&tables.scoreboard)?
+            } else {
+                serde_json::to_string(&tables.scoreboard)?
+            };
+            let output = args.output.unwrap_or_else(|| "OUT.json".into());
+            let mut output = File::create(output)?;
+            output.write_all(json.as_bytes())?;
+        }
+        #[cfg(feature = "parquet")]
+        Format::Parquet => {
+            let stream = DemoStream::new(buf)?;
+            let tables = Tables::from_frames(stream, args.sample_rate, round_options)?;
+            tables.write_parquet(&args.output.unwrap_or_else(|| "OUT".into()))?;
+        }
+        #[cfg(feature = "sqlite")]
+        Format::Sqlite => {
+            let mut raw = Vec::new();
+            buf.read_to_end(&mut raw)?;
 
-    let output = args.output;
-    let mut output = File::create(output)?;
-    output.write_all(json.as_bytes())?;
+            let hash = csgo_demo_parser::sqlite::demo_hash(&raw);
+            let stream = DemoStream::new(raw.as_slice())?;
+            let header = stream.header.clone();
+            let tables = Tables::from_frames(stream, args.sample_rate, round_options)?;
+            let output = args.output.unwrap_or_else(|| "OUT.sqlite".into());
+            csgo_demo_parser::sqlite::write_sqlite(&output, &hash, &header, &tables)?;
+        }
+    }
 
     Ok(())
 }
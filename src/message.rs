@@ -3,6 +3,7 @@
 use serde::Serialize;
 
 use crate::cursor::Cursor;
+use crate::error::{DemoError, Result};
 use crate::protos;
 
 #[derive(Debug, Serialize)]
@@ -47,16 +48,12 @@ pub enum Message {
     BroadcastCommand(protos::CsvcMsgBroadcastCommand),
 }
 
-fn make<T: Default + prost::Message>(data: &Cursor, length: u32) -> anyhow::Result<T> {
+fn make<T: Default + prost::Message>(data: &Cursor, length: u32) -> Result<T> {
     Ok(T::decode(data.read_bytes(length as usize)?.as_ref())?)
 }
 
 impl Message {
-    pub fn parse(data: &Cursor, message_type: i32, length: u32) -> anyhow::Result<Message> {
-        //protos::NetMessages::from_i32(which).map(|nm|
-        // MessageType::Net(nm)).or_else(||
-        // protos::SvcMessages::from_i32(which).unwrap_or_else(|| anyhow::anyhow!("Bad
-        // ordinal for Message Type: `{which}`.")))
+    pub fn parse(data: &Cursor, message_type: i32, length: u32) -> Result<Message> {
         let msg = if let Some(nm) = protos::NetMessages::from_i32(message_type) {
             match nm {
                 protos::NetMessages::NetNop => Message::Nop(make(data, length)?),
@@ -122,7 +119,7 @@ impl Message {
                 }
             }
         } else {
-            anyhow::bail!("Bad ordinal for Message Type: `{message_type}`.");
+            return Err(DemoError::UnknownMessage(message_type));
         };
         Ok(msg)
     }
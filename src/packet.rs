@@ -1,30 +1,32 @@
 use serde::Serialize;
 
 use crate::cursor::Cursor;
+use crate::error::{DemoError, Result};
 use crate::message::Message;
 
-/// An unused struct, only observed as a bunch of 0s.
+/// The recording player's view, for one split screen slot.
+/// All zeros in GOTV demos.
 #[derive(Debug, Serialize)]
 pub struct Split {
-    flags: i32,
+    pub flags: i32,
     /// A Vector3
-    view_origin: (f32, f32, f32),
+    pub view_origin: (f32, f32, f32),
     /// A QAngle
-    view_angles: (f32, f32, f32),
+    pub view_angles: (f32, f32, f32),
     /// A QAngle
-    local_view_angles: (f32, f32, f32),
+    pub local_view_angles: (f32, f32, f32),
 
     /// A Vector3
-    view_origin_2: (f32, f32, f32),
+    pub view_origin_2: (f32, f32, f32),
     /// A QAngle
-    view_angles_2: (f32, f32, f32),
+    pub view_angles_2: (f32, f32, f32),
     /// A QAngle
-    local_view_angles_2: (f32, f32, f32),
+    pub local_view_angles_2: (f32, f32, f32),
 }
 
 impl Split {
-    pub fn parse(data: &Cursor) -> anyhow::Result<Split> {
-        fn read_triple_f32(data: &Cursor) -> anyhow::Result<(f32, f32, f32)> {
+    pub fn parse(data: &Cursor) -> Result<Split> {
+        fn read_triple_f32(data: &Cursor) -> Result<(f32, f32, f32)> {
             let a = data.read_f32()?;
             let b = data.read_f32()?;
             let c = data.read_f32()?;
@@ -59,14 +61,17 @@ impl Split {
     }
 }
 
-/// An unused struct, only observed as a bunch of 0s.
+/// The size of a `CommandInfo` on disk, in bytes.
+pub(crate) const COMMAND_INFO_SIZE: usize = 2 * (4 + 6 * 3 * 4);
+
+/// The view of the player recording the demo. Only POV demos fill it in.
 #[derive(Debug, Serialize)]
 pub struct CommandInfo {
-    u: (Split, Split),
+    pub u: (Split, Split),
 }
 
 impl CommandInfo {
-    pub fn parse(data: &Cursor) -> anyhow::Result<CommandInfo> {
+    pub fn parse(data: &Cursor) -> Result<CommandInfo> {
         let a = Split::parse(data)?;
         let b = Split::parse(data)?;
         Ok(CommandInfo { u: (a, b) })
@@ -79,31 +84,28 @@ impl CommandInfo {
 
 #[derive(Debug, Serialize)]
 pub struct Packet {
-    /// I have never seen these as nonzero values.
+    /// Zero in GOTV demos. Left out of the JSON, which would be mostly zeros.
     #[serde(skip)]
-    _command_info: CommandInfo,
-    seq_nr_in: u32,
-    seq_nr_out: u32,
-    messages: Vec<Message>,
+    pub command_info: CommandInfo,
+    pub seq_nr_in: u32,
+    pub seq_nr_out: u32,
+    pub messages: Vec<Message>,
 }
 
 impl Packet {
-    pub fn parse(cursor: &Cursor) -> anyhow::Result<Packet> {
+    pub fn parse(cursor: &Cursor) -> Result<Packet> {
         let command_info = CommandInfo::parse(cursor)?;
-        if command_info.is_nonzero() {
-            println!("Nonzero command_info: {command_info:#?}");
-        }
         let seq_nr_in = cursor.read_u32()?;
         let seq_nr_out = cursor.read_u32()?;
-        let chunk_size = cursor.read_i32()?;
-        let chunk = cursor.chunk_bytes(chunk_size as usize)?;
+        let chunk_size = cursor.read_length("packet", true)?;
+        let chunk = cursor.chunk_bytes(chunk_size)?;
         let mut messages = vec![];
         // While we have data left, read!
         while !chunk.is_empty() {
             messages.push(parse_message(&chunk)?);
         }
         Ok(Packet {
-            _command_info: command_info,
+            command_info,
             seq_nr_in,
             seq_nr_out,
             messages,
@@ -111,10 +113,48 @@ impl Packet {
     }
 }
 
-pub fn parse_message(chunk: &Cursor) -> anyhow::Result<Message> {
+pub fn parse_message(chunk: &Cursor) -> Result<Message> {
     let cmd = chunk.read_protobuf_var_int()?;
+    let byte_offset = chunk.byte_pos();
     let length = chunk.read_protobuf_var_int()?;
+    // A varint can't be trusted to fit in what is left of the packet.
+    if length < 0 || length as usize > chunk.remaining_bits() / 8 {
+        return Err(DemoError::BadLength {
+            what: "message",
+            length,
+            byte_offset,
+        });
+    }
     let inner_chunk = chunk.chunk_bytes(length as usize)?;
     let msg = Message::parse(&inner_chunk, cmd, length as u32)?;
     Ok(msg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_message_lengths() {
+        // A command, then 5 bytes of message, with only 2 left.
+        let oversized = [0x04, 0x05, 0x08, 0x01];
+        assert!(matches!(
+            parse_message(&Cursor::with_offset(&oversized, 100)),
+            Err(DemoError::BadLength {
+                what: "message",
+                length: 5,
+                byte_offset: 101,
+            })
+        ));
+
+        // Far more than the packet could hold, as a 4 byte varint.
+        let huge = [0x04, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert!(matches!(
+            parse_message(&Cursor::new(&huge)),
+            Err(DemoError::BadLength {
+                length: 0x0FFF_FFFF,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+//! Player positions over time, one row per player per sampled tick.
+//! Feed a [`PositionSampler`] the [`GameState`] after every frame.
+
+use serde::Serialize;
+
+use crate::data_tables::DataTable;
+use crate::entities::{handle_index, Entity};
+use crate::game_state::GameState;
+use crate::props::PropValue;
+use crate::steam_id::SteamId;
+
+/// `MAX_PLAYERS` in the engine. Players are always the first entities after the world.
+const MAX_PLAYERS: usize = 64;
+/// `FL_ONGROUND` in `m_fFlags`.
+const FL_ONGROUND: i32 = 1 << 0;
+/// `FL_DUCKING` in `m_fFlags`.
+const FL_DUCKING: i32 = 1 << 1;
+/// `LIFE_ALIVE` in `m_lifeState`.
+const LIFE_ALIVE: i32 = 0;
+
+/// Where a player was, and what they were doing, at one tick.
+/// Props that haven't been sent yet are read as zero.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlayerPosition {
+    pub tick: u32,
+    pub entity_index: usize,
+    /// From the roster, if the player is in it.
+    pub user_id: Option<i32>,
+    pub steam_id: Option<SteamId>,
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub velocity_x: f32,
+    pub velocity_y: f32,
+    pub velocity_z: f32,
+    pub health: i32,
+    pub armor: i32,
+    /// The active weapon, like `ak47` or `knife`, from its server class name.
+    pub weapon: Option<String>,
+    pub team: i32,
+    pub is_alive: bool,
+    pub is_ducking: bool,
+    pub is_on_ground: bool,
+}
+
+impl PlayerPosition {
+    /// A row for every player entity in the game.
+    pub fn all(state: &GameState, tick: u32) -> Vec<PlayerPosition> {
+        let Some(data_table) = &state.data_table else {
+            return vec![];
+        };
+        (1..=MAX_PLAYERS)
+            .filter_map(|index| state.entities.get(index))
+            .filter(|entity| class_name(data_table, entity) == Some("CCSPlayer"))
+            .map(|entity| PlayerPosition::new(state, data_table, entity, tick))
+            .collect()
+    }
+
+    /// The row for one player entity, if it exists.
+    pub fn of(state: &GameState, entity_index: usize, tick: u32) -> Option<PlayerPosition> {
+        let data_table = state.data_table.as_ref()?;
+        let entity = state.entities.get(entity_index)?;
+        if class_name(data_table, entity) != Some("CCSPlayer") {
+            return None;
+        }
+        Some(PlayerPosition::new(state, data_table, entity, tick))
+    }
+
+    fn new(
+        state: &GameState,
+        data_table: &DataTable,
+        entity: &Entity,
+        tick: u32,
+    ) -> PlayerPosition {
+        let class = &data_table.server_classes[entity.class_id];
+        let prop = |path: &str| entity.prop(class, path);
+        let float = |path: &str| prop(path).and_then(PropValue::as_float).unwrap_or_default();
+        let int = |path: &str| prop(path).and_then(PropValue::as_int).unwrap_or_default();
+
+        // GOTV gets everyone's origin in `csnonlocaldata`,
+        // the player recording a POV demo gets their own in `cslocaldata`.
+        let (xy, z) = match prop("csnonlocaldata.m_vecOrigin") {
+            Some(xy) => (Some(xy), prop("csnonlocaldata.m_vecOrigin[2]")),
+            None => (
+                prop("cslocaldata.m_vecOrigin"),
+                prop("cslocaldata.m_vecOrigin[2]"),
+            ),
+        };
+        let [x, y] = xy.and_then(PropValue::as_vector_xy).unwrap_or_default();
+        let z = z.and_then(PropValue::as_float).unwrap_or_default();
+
+        let weapon = prop("m_hActiveWeapon")
+            .and_then(PropValue::as_int)
+            .and_then(handle_index)
+            .and_then(|index| state.entities.get(index))
+            .and_then(|weapon| class_name(data_table, weapon))
+            .map(weapon_name);
+        let player = state.roster.by_entity(entity.index);
+        let flags = int("m_fFlags");
+
+        PlayerPosition {
+            tick,
+            entity_index: entity.index,
+            user_id: player.map(|p| p.user_id),
+            steam_id: player.and_then(|p| p.steam_id),
+            name: player.map(|p| p.name.clone()).unwrap_or_default(),
+            x,
+            y,
+            z,
+            pitch: float("m_angEyeAngles[0]"),
+            yaw: float("m_angEyeAngles[1]"),
+            velocity_x: float("localdata.m_vecVelocity[0]"),
+            velocity_y: float("localdata.m_vecVelocity[1]"),
+            velocity_z: float("localdata.m_vecVelocity[2]"),
+            health: int("m_iHealth"),
+            armor: int("m_ArmorValue"),
+            weapon,
+            team: int("m_iTeamNum"),
+            is_alive: int("m_lifeState") == LIFE_ALIVE,
+            is_ducking: flags & FL_DUCKING != 0,
+            is_on_ground: flags & FL_ONGROUND != 0,
+        }
+    }
+}
+
+pub(crate) fn class_name<'a>(data_table: &'a DataTable, entity: &Entity) -> Option<&'a str> {
+    let class = data_table.server_classes.get(entity.class_id)?;
+    Some(&class.name)
+}
+
+/// `CWeaponAWP` becomes `awp`, `CAK47` becomes `ak47`,
+/// which is close to the names game events use.
+pub(crate) fn weapon_name(class_name: &str) -> String {
+    class_name
+        .strip_prefix("CWeapon")
+        .or_else(|| class_name.strip_prefix('C'))
+        .unwrap_or(class_name)
+        .to_lowercase()
+}
+
+/// Takes a row per player every `every` ticks.
+#[derive(Debug, Clone)]
+pub struct PositionSampler {
+    every: u32,
+    next_tick: u32,
+}
+
+impl PositionSampler {
+    /// Sample every `every` ticks. Zero is the same as one, every tick.
+    pub fn new(every: u32) -> PositionSampler {
+        PositionSampler {
+            every: every.max(1),
+            next_tick: 0,
+        }
+    }
+
+    /// The rows for this tick, if it's time for a sample.
+    /// Frames can share a tick, but only the first of them is sampled.
+    pub fn sample(&mut self, state: &GameState, tick: u32) -> Vec<PlayerPosition> {
+        if tick < self.next_tick {
+            return vec![];
+        }
+        let rows = PlayerPosition::all(state, tick);
+        if !rows.is_empty() {
+            self.next_tick = tick + self.every;
+        }
+        rows
+    }
+}
@@ -0,0 +1,558 @@
+//! Decoding of SendProp values, as sent in entity updates.
+//! This follows the engine's `dt_encode.cpp` and the `bitbuf` coordinate
+//! readers, bit for bit.
+
+use serde::Serialize;
+
+use crate::cursor::Cursor;
+use crate::data_tables::{Prop, PropFlags, PropTypes};
+use crate::error::{DemoError, Result};
+use crate::protos::csvc_msg_send_table::SendpropT;
+
+/// `DT_MAX_STRING_BITS` in the engine.
+const MAX_STRING_BITS: usize = 9;
+
+const COORD_INTEGER_BITS: usize = 14;
+const COORD_INTEGER_BITS_MP: usize = 11;
+const COORD_FRACTIONAL_BITS: usize = 5;
+const COORD_FRACTIONAL_BITS_MP_LOWPRECISION: usize = 3;
+const COORD_RESOLUTION: f32 = 1.0 / (1 << COORD_FRACTIONAL_BITS) as f32;
+const COORD_RESOLUTION_LOWPRECISION: f32 =
+    1.0 / (1 << COORD_FRACTIONAL_BITS_MP_LOWPRECISION) as f32;
+
+const NORMAL_FRACTIONAL_BITS: usize = 11;
+const NORMAL_RESOLUTION: f32 = 1.0 / ((1 << NORMAL_FRACTIONAL_BITS) - 1) as f32;
+
+/// The decoded value of a single prop.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum PropValue {
+    Int(i32),
+    Int64(i64),
+    Float(f32),
+    Vector([f32; 3]),
+    VectorXY([f32; 2]),
+    String(String),
+    Array(Vec<PropValue>),
+}
+
+impl PropValue {
+    /// Decode a value for a flattened prop.
+    pub fn decode(data: &Cursor, prop: &Prop) -> Result<PropValue> {
+        decode_prop(data, &prop.inner, prop.element.as_deref())
+    }
+
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            PropValue::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f32> {
+        match self {
+            PropValue::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    pub fn as_vector(&self) -> Option<[f32; 3]> {
+        match self {
+            PropValue::Vector(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_vector_xy(&self) -> Option<[f32; 2]> {
+        match self {
+            PropValue::VectorXY(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            PropValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// `element` is the template prop for the elements of an array.
+fn decode_prop(data: &Cursor, prop: &SendpropT, element: Option<&SendpropT>) -> Result<PropValue> {
+    let flags = PropFlags::from_i32(prop.flags())?;
+    Ok(match PropTypes::from_i32(prop.r#type())? {
+        PropTypes::Int => PropValue::Int(decode_int(data, prop, flags)?),
+        PropTypes::Int64 => PropValue::Int64(decode_int64(data, prop, flags)?),
+        PropTypes::Float => PropValue::Float(decode_float(data, prop, flags)?),
+        PropTypes::Vector => PropValue::Vector(decode_vector(data, prop, flags)?),
+        PropTypes::VectorXY => {
+            let x = decode_float(data, prop, flags)?;
+            let y = decode_float(data, prop, flags)?;
+            PropValue::VectorXY([x, y])
+        }
+        PropTypes::String => {
+            let length = data.read_ubits(MAX_STRING_BITS)?;
+            let bytes = data.read_bytes(length as usize)?;
+            PropValue::String(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        PropTypes::Array => {
+            let element = element
+                .ok_or_else(|| DemoError::MissingArrayElement(prop.var_name().to_string()))?;
+            let mut max_elements = prop.num_elements();
+            let mut num_bits = 1;
+            while max_elements > 1 {
+                max_elements >>= 1;
+                num_bits += 1;
+            }
+            let count = data.read_ubits(num_bits)?;
+            let mut values = Vec::with_capacity(count as usize);
+            for _ in 0..count {
+                values.push(decode_prop(data, element, None)?);
+            }
+            PropValue::Array(values)
+        }
+        PropTypes::DataTable | PropTypes::NUMSendPropTypes => {
+            return Err(DemoError::BadPropType(prop.r#type()))
+        }
+    })
+}
+
+fn decode_int(data: &Cursor, prop: &SendpropT, flags: PropFlags) -> Result<i32> {
+    let unsigned = flags.contains(PropFlags::UNSIGNED);
+    if flags.contains(PropFlags::VARINT) {
+        if unsigned {
+            Ok(data.read_var_u32()? as i32)
+        } else {
+            data.read_signed_var_i32()
+        }
+    } else if unsigned {
+        Ok(data.read_ubits(prop.num_bits() as usize)? as i32)
+    } else {
+        data.read_sbits(prop.num_bits() as usize)
+    }
+}
+
+fn decode_int64(data: &Cursor, prop: &SendpropT, flags: PropFlags) -> Result<i64> {
+    let unsigned = flags.contains(PropFlags::UNSIGNED);
+    if flags.contains(PropFlags::VARINT) {
+        return if unsigned {
+            Ok(data.read_var_u64()? as i64)
+        } else {
+            data.read_signed_var_i64()
+        };
+    }
+    let bits = prop.num_bits() as usize;
+    let (negative, low, high) = if unsigned {
+        let low = data.read_ubits(32)?;
+        let high = data.read_ubits(bits.saturating_sub(32))?;
+        (false, low, high)
+    } else {
+        let negative = data.read_bit_bool()?;
+        let low = data.read_ubits(32)?;
+        let high = data.read_ubits(bits.saturating_sub(32 + 1))?;
+        (negative, low, high)
+    };
+    let n = ((high as i64) << 32) | low as i64;
+    Ok(if negative { -n } else { n })
+}
+
+/// `ROUND_DOWN` and `ROUND_UP` only matter when encoding,
+/// the server has already folded them into `low_value` and `high_value`.
+fn decode_float(data: &Cursor, prop: &SendpropT, flags: PropFlags) -> Result<f32> {
+    let bits = prop.num_bits() as usize;
+    if flags.contains(PropFlags::COORD) {
+        read_bit_coord(data)
+    } else if flags.contains(PropFlags::COORD_MP) {
+        read_bit_coord_mp(data, false, false)
+    } else if flags.contains(PropFlags::COORD_MP_LOW_PRECISION) {
+        read_bit_coord_mp(data, false, true)
+    } else if flags.contains(PropFlags::COORD_MP_INTEGRAL) {
+        read_bit_coord_mp(data, true, false)
+    } else if flags.contains(PropFlags::NO_SCALE) {
+        data.read_f32()
+    } else if flags.contains(PropFlags::NORMAL) {
+        read_bit_normal(data)
+    } else if flags.contains(PropFlags::CELL_COORD) {
+        read_bit_cell_coord(data, bits, false, false)
+    } else if flags.contains(PropFlags::CELL_COORD_LOWPRECISION) {
+        read_bit_cell_coord(data, bits, false, true)
+    } else if flags.contains(PropFlags::CELL_COORD_INTEGRAL) {
+        read_bit_cell_coord(data, bits, true, false)
+    } else {
+        let interp = data.read_ubits(bits)?;
+        let fraction = interp as f32 / ((1u64 << bits) - 1) as f32;
+        Ok(prop.low_value() + (prop.high_value() - prop.low_value()) * fraction)
+    }
+}
+
+/// Normals only send X and Y, and the sign of Z.
+/// `XYZE` vectors are sent the same as any other vector.
+fn decode_vector(data: &Cursor, prop: &SendpropT, flags: PropFlags) -> Result<[f32; 3]> {
+    let x = decode_float(data, prop, flags)?;
+    let y = decode_float(data, prop, flags)?;
+    let z = if flags.contains(PropFlags::NORMAL) {
+        let negative = data.read_bit_bool()?;
+        let xy = x * x + y * y;
+        let z = if xy < 1.0 { (1.0 - xy).sqrt() } else { 0.0 };
+        if negative {
+            -z
+        } else {
+            z
+        }
+    } else {
+        decode_float(data, prop, flags)?
+    };
+    Ok([x, y, z])
+}
+
+/// `CBitRead::ReadBitCoord`
+fn read_bit_coord(data: &Cursor) -> Result<f32> {
+    let has_int = data.read_bit_bool()?;
+    let has_fract = data.read_bit_bool()?;
+    if !has_int && !has_fract {
+        return Ok(0.0);
+    }
+    let negative = data.read_bit_bool()?;
+    let int = if has_int {
+        data.read_ubits(COORD_INTEGER_BITS)? + 1
+    } else {
+        0
+    };
+    let fract = if has_fract {
+        data.read_ubits(COORD_FRACTIONAL_BITS)?
+    } else {
+        0
+    };
+    let value = int as f32 + fract as f32 * COORD_RESOLUTION;
+    Ok(if negative { -value } else { value })
+}
+
+/// `CBitRead::ReadBitCoordMP`
+fn read_bit_coord_mp(data: &Cursor, integral: bool, low_precision: bool) -> Result<f32> {
+    let in_bounds = data.read_bit_bool()?;
+    let int_bits = if in_bounds {
+        COORD_INTEGER_BITS_MP
+    } else {
+        COORD_INTEGER_BITS
+    };
+    let has_int = data.read_bit_bool()?;
+    let (negative, value) = if integral {
+        if !has_int {
+            return Ok(0.0);
+        }
+        let negative = data.read_bit_bool()?;
+        (negative, (data.read_ubits(int_bits)? + 1) as f32)
+    } else {
+        let negative = data.read_bit_bool()?;
+        let int = if has_int {
+            data.read_ubits(int_bits)? + 1
+        } else {
+            0
+        };
+        let (fract_bits, resolution) = if low_precision {
+            (
+                COORD_FRACTIONAL_BITS_MP_LOWPRECISION,
+                COORD_RESOLUTION_LOWPRECISION,
+            )
+        } else {
+            (COORD_FRACTIONAL_BITS, COORD_RESOLUTION)
+        };
+        let fract = data.read_ubits(fract_bits)?;
+        (negative, int as f32 + fract as f32 * resolution)
+    };
+    Ok(if negative { -value } else { value })
+}
+
+/// `CBitRead::ReadBitCellCoord`
+fn read_bit_cell_coord(
+    data: &Cursor,
+    bits: usize,
+    integral: bool,
+    low_precision: bool,
+) -> Result<f32> {
+    let int = data.read_ubits(bits)?;
+    if integral {
+        return Ok(int as f32);
+    }
+    let (fract_bits, resolution) = if low_precision {
+        (
+            COORD_FRACTIONAL_BITS_MP_LOWPRECISION,
+            COORD_RESOLUTION_LOWPRECISION,
+        )
+    } else {
+        (COORD_FRACTIONAL_BITS, COORD_RESOLUTION)
+    };
+    let fract = data.read_ubits(fract_bits)?;
+    Ok(int as f32 + fract as f32 * resolution)
+}
+
+/// `CBitRead::ReadBitNormal`
+fn read_bit_normal(data: &Cursor) -> Result<f32> {
+    let negative = data.read_bit_bool()?;
+    let fract = data.read_ubits(NORMAL_FRACTIONAL_BITS)?;
+    let value = fract as f32 * NORMAL_RESOLUTION;
+    Ok(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use bitbuffer::{BitWriteStream, LittleEndian};
+
+    use super::*;
+
+    type Writer<'a> = BitWriteStream<'a, LittleEndian>;
+
+    /// The bytes written by `f`, in the engine's bit order.
+    fn bits(f: impl FnOnce(&mut Writer)) -> Vec<u8> {
+        let mut data = vec![];
+        let mut writer = BitWriteStream::new(&mut data, LittleEndian);
+        f(&mut writer);
+        data
+    }
+
+    fn prop(kind: PropTypes, flags: PropFlags, num_bits: i32) -> SendpropT {
+        SendpropT {
+            r#type: Some(kind as i32),
+            flags: Some(flags.bits() as i32),
+            num_bits: Some(num_bits),
+            ..SendpropT::default()
+        }
+    }
+
+    fn decode(data: &[u8], prop: &SendpropT) -> PropValue {
+        decode_prop(&Cursor::new(data), prop, None).unwrap()
+    }
+
+    fn float(data: &[u8], flags: PropFlags, num_bits: i32) -> f32 {
+        decode(data, &prop(PropTypes::Float, flags, num_bits))
+            .as_float()
+            .unwrap()
+    }
+
+    #[test]
+    fn coord() {
+        let data = bits(|w| {
+            w.write_bool(true).unwrap(); // has int
+            w.write_bool(true).unwrap(); // has fraction
+            w.write_bool(true).unwrap(); // negative
+            w.write_int(4u32, COORD_INTEGER_BITS).unwrap();
+            w.write_int(16u32, COORD_FRACTIONAL_BITS).unwrap();
+        });
+        assert_eq!(float(&data, PropFlags::COORD, 0), -5.5);
+
+        // Neither part is sent for zero, and nothing comes after the two bits.
+        let data = bits(|w| w.write_int(0u8, 2).unwrap());
+        let cursor = Cursor::new(&data);
+        let prop = prop(PropTypes::Float, PropFlags::COORD, 0);
+        assert_eq!(
+            decode_prop(&cursor, &prop, None).unwrap(),
+            PropValue::Float(0.0)
+        );
+        assert_eq!(cursor.remaining_bits(), 6);
+    }
+
+    #[test]
+    fn coord_mp() {
+        let data = bits(|w| {
+            w.write_bool(true).unwrap(); // in bounds
+            w.write_bool(true).unwrap(); // has int
+            w.write_bool(false).unwrap(); // positive
+            w.write_int(98u32, COORD_INTEGER_BITS_MP).unwrap();
+            w.write_int(8u32, COORD_FRACTIONAL_BITS).unwrap();
+        });
+        assert_eq!(float(&data, PropFlags::COORD_MP, 0), 99.25);
+
+        // Out of bounds coordinates get the full integer bits.
+        let data = bits(|w| {
+            w.write_bool(false).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_int(2999u32, COORD_INTEGER_BITS).unwrap();
+            w.write_int(0u32, COORD_FRACTIONAL_BITS).unwrap();
+        });
+        assert_eq!(float(&data, PropFlags::COORD_MP, 0), -3000.0);
+    }
+
+    #[test]
+    fn coord_mp_low_precision() {
+        let data = bits(|w| {
+            w.write_bool(true).unwrap(); // in bounds
+            w.write_bool(false).unwrap(); // no int
+            w.write_bool(true).unwrap(); // negative
+            w.write_int(4u32, COORD_FRACTIONAL_BITS_MP_LOWPRECISION)
+                .unwrap();
+        });
+        assert_eq!(float(&data, PropFlags::COORD_MP_LOW_PRECISION, 0), -0.5);
+    }
+
+    #[test]
+    fn coord_mp_integral() {
+        let data = bits(|w| {
+            w.write_bool(true).unwrap(); // in bounds
+            w.write_bool(true).unwrap(); // has int
+            w.write_bool(true).unwrap(); // negative
+            w.write_int(41u32, COORD_INTEGER_BITS_MP).unwrap();
+        });
+        assert_eq!(float(&data, PropFlags::COORD_MP_INTEGRAL, 0), -42.0);
+
+        // Without an int there is no sign bit either.
+        let data = bits(|w| w.write_int(0b01u8, 2).unwrap());
+        let cursor = Cursor::new(&data);
+        let prop = prop(PropTypes::Float, PropFlags::COORD_MP_INTEGRAL, 0);
+        assert_eq!(
+            decode_prop(&cursor, &prop, None).unwrap(),
+            PropValue::Float(0.0)
+        );
+        assert_eq!(cursor.remaining_bits(), 6);
+    }
+
+    #[test]
+    fn cell_coord() {
+        let data = bits(|w| {
+            w.write_int(300u32, 10).unwrap();
+            w.write_int(24u32, COORD_FRACTIONAL_BITS).unwrap();
+        });
+        assert_eq!(float(&data, PropFlags::CELL_COORD, 10), 300.75);
+
+        let data = bits(|w| {
+            w.write_int(300u32, 10).unwrap();
+            w.write_int(2u32, COORD_FRACTIONAL_BITS_MP_LOWPRECISION)
+                .unwrap();
+        });
+        assert_eq!(float(&data, PropFlags::CELL_COORD_LOWPRECISION, 10), 300.25);
+
+        let data = bits(|w| w.write_int(300u32, 10).unwrap());
+        let cursor = Cursor::new(&data);
+        let prop = prop(PropTypes::Float, PropFlags::CELL_COORD_INTEGRAL, 10);
+        assert_eq!(
+            decode_prop(&cursor, &prop, None).unwrap(),
+            PropValue::Float(300.0)
+        );
+        assert_eq!(cursor.remaining_bits(), 6);
+    }
+
+    #[test]
+    fn scaled_and_unscaled_floats() {
+        let mut p = prop(PropTypes::Float, PropFlags::empty(), 8);
+        p.low_value = Some(-1.0);
+        p.high_value = Some(1.0);
+        let data = bits(|w| w.write_int(255u32, 8).unwrap());
+        assert_eq!(decode(&data, &p), PropValue::Float(1.0));
+        let data = bits(|w| w.write_int(0u32, 8).unwrap());
+        assert_eq!(decode(&data, &p), PropValue::Float(-1.0));
+
+        let data = bits(|w| w.write_float(1.5f32).unwrap());
+        assert_eq!(float(&data, PropFlags::NO_SCALE, 32), 1.5);
+    }
+
+    #[test]
+    fn normal() {
+        let data = bits(|w| {
+            w.write_bool(true).unwrap();
+            w.write_int(2047u32, NORMAL_FRACTIONAL_BITS).unwrap();
+        });
+        assert_eq!(float(&data, PropFlags::NORMAL, 0), -1.0);
+
+        // Z is never sent, only its sign.
+        let data = bits(|w| {
+            w.write_bool(false).unwrap();
+            w.write_int(1228u32, NORMAL_FRACTIONAL_BITS).unwrap(); // x ~ 0.6
+            w.write_bool(false).unwrap();
+            w.write_int(0u32, NORMAL_FRACTIONAL_BITS).unwrap(); // y = 0
+            w.write_bool(true).unwrap(); // z is negative
+        });
+        let value = decode(&data, &prop(PropTypes::Vector, PropFlags::NORMAL, 0));
+        let [x, y, z] = value.as_vector().unwrap();
+        assert_eq!(x, 1228.0 * NORMAL_RESOLUTION);
+        assert_eq!(y, 0.0);
+        assert_eq!(z, -(1.0 - x * x).sqrt());
+    }
+
+    #[test]
+    fn vector_xy() {
+        let data = bits(|w| {
+            w.write_float(1.0f32).unwrap();
+            w.write_float(-2.0f32).unwrap();
+        });
+        let value = decode(&data, &prop(PropTypes::VectorXY, PropFlags::NO_SCALE, 32));
+        assert_eq!(value, PropValue::VectorXY([1.0, -2.0]));
+    }
+
+    #[test]
+    fn ints() {
+        let data = bits(|w| w.write_int(0b11101u32, 5).unwrap());
+        let signed = prop(PropTypes::Int, PropFlags::empty(), 5);
+        let unsigned = prop(PropTypes::Int, PropFlags::UNSIGNED, 5);
+        assert_eq!(decode(&data, &signed), PropValue::Int(-3));
+        assert_eq!(decode(&data, &unsigned), PropValue::Int(29));
+    }
+
+    #[test]
+    fn varints() {
+        // 300, as a protobuf varint.
+        let data = [0xAC, 0x02];
+        let unsigned = prop(PropTypes::Int, PropFlags::VARINT | PropFlags::UNSIGNED, 0);
+        assert_eq!(decode(&data, &unsigned), PropValue::Int(300));
+        // Signed varints are zigzag encoded, so 3 is -2.
+        let signed = prop(PropTypes::Int, PropFlags::VARINT, 0);
+        assert_eq!(decode(&[0x03], &signed), PropValue::Int(-2));
+
+        let unsigned = prop(PropTypes::Int64, PropFlags::VARINT | PropFlags::UNSIGNED, 0);
+        let data = [0xFF, 0xFF, 0xFF, 0xFF, 0x1F];
+        assert_eq!(decode(&data, &unsigned), PropValue::Int64(0x1_FFFF_FFFF));
+        let signed = prop(PropTypes::Int64, PropFlags::VARINT, 0);
+        assert_eq!(decode(&[0x01], &signed), PropValue::Int64(-1));
+    }
+
+    #[test]
+    fn int64() {
+        let data = bits(|w| {
+            w.write_int(0xDEAD_BEEFu32, 32).unwrap();
+            w.write_int(0x12u32, 8).unwrap();
+        });
+        let unsigned = prop(PropTypes::Int64, PropFlags::UNSIGNED, 40);
+        assert_eq!(decode(&data, &unsigned), PropValue::Int64(0x12_DEAD_BEEF));
+
+        // A sign bit, and then the magnitude in one bit less.
+        let data = bits(|w| {
+            w.write_bool(true).unwrap();
+            w.write_int(5u32, 32).unwrap();
+            w.write_int(1u32, 7).unwrap();
+        });
+        let signed = prop(PropTypes::Int64, PropFlags::empty(), 40);
+        assert_eq!(decode(&data, &signed), PropValue::Int64(-((1 << 32) | 5)));
+    }
+
+    #[test]
+    fn string() {
+        let data = bits(|w| {
+            w.write_int(3u32, MAX_STRING_BITS).unwrap();
+            w.write_bytes(b"abc").unwrap();
+        });
+        let value = decode(&data, &prop(PropTypes::String, PropFlags::empty(), 0));
+        assert_eq!(value, PropValue::String("abc".to_string()));
+    }
+
+    #[test]
+    fn array() {
+        let mut array = prop(PropTypes::Array, PropFlags::empty(), 0);
+        array.num_elements = Some(4);
+        let element = prop(PropTypes::Int, PropFlags::UNSIGNED, 4);
+        // Up to 4 elements takes a 3 bit count.
+        let data = bits(|w| {
+            w.write_int(2u32, 3).unwrap();
+            w.write_int(7u32, 4).unwrap();
+            w.write_int(9u32, 4).unwrap();
+        });
+        let cursor = Cursor::new(&data);
+        let value = decode_prop(&cursor, &array, Some(&element)).unwrap();
+        assert_eq!(
+            value,
+            PropValue::Array(vec![PropValue::Int(7), PropValue::Int(9)])
+        );
+        assert_eq!(cursor.remaining_bits(), 16 - 11);
+
+        let err = decode_prop(&Cursor::new(&data), &array, None).unwrap_err();
+        assert!(matches!(err, DemoError::MissingArrayElement(_)));
+    }
+}
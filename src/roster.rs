@@ -0,0 +1,348 @@
+//! Every player seen over the course of a demo.
+//! Event keys like `userid` only name a connection, so the roster ties them
+//! back to a player that stays the same across reconnects.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::events::{DemoEvent, Event};
+use crate::steam_id::SteamId;
+use crate::string_tables::PlayerInfo;
+
+/// `TEAM_TERRORIST`, the [`Player::team`] of terrorists.
+pub(crate) const TEAM_T: i32 = 2;
+/// `TEAM_CT`, the [`Player::team`] of counter-terrorists.
+pub(crate) const TEAM_CT: i32 = 3;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Player {
+    /// The player's entry in the `userinfo` table. Their entity index is one more.
+    pub slot: usize,
+    /// The `userid` of the player's latest connection.
+    pub user_id: i32,
+    /// The player's Steam account, once it is known.
+    pub steam_id: Option<SteamId>,
+    pub name: String,
+    /// Every name the player used, with the tick it was first seen.
+    pub names: Vec<(u32, String)>,
+    pub is_bot: bool,
+    pub is_hltv: bool,
+    /// The player's current team: 0 for none, 1 for spectators, 2 for T and 3 for CT.
+    pub team: i32,
+    /// Every team the player joined, with the tick they joined it.
+    pub teams: Vec<(u32, i32)>,
+    /// The tick of the player's latest connection, or when they were first seen.
+    pub connected_tick: u32,
+    /// The tick the player left, if they have.
+    pub disconnected_tick: Option<u32>,
+}
+
+impl Player {
+    fn new(slot: usize, user_id: i32, tick: u32) -> Player {
+        Player {
+            slot,
+            user_id,
+            steam_id: None,
+            name: String::new(),
+            names: vec![],
+            is_bot: false,
+            is_hltv: false,
+            team: 0,
+            teams: vec![],
+            connected_tick: tick,
+            disconnected_tick: None,
+        }
+    }
+
+    pub fn entity_index(&self) -> usize {
+        self.slot + 1
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.disconnected_tick.is_none()
+    }
+
+    fn rename(&mut self, name: &str, tick: u32) {
+        if !name.is_empty() && self.name != name {
+            self.name = name.to_string();
+            self.names.push((tick, self.name.clone()));
+        }
+    }
+}
+
+/// Keeps track of players, from the `userinfo` string table
+/// and the `player_connect`, `player_disconnect` and `player_team` events.
+#[derive(Debug, Clone, Default)]
+pub struct Roster {
+    /// Everyone ever seen, in the order they were first seen.
+    players: Vec<Player>,
+    /// Every `userid` seen, including those of earlier connections.
+    user_ids: HashMap<i32, usize>,
+    /// The currently connected players, by slot.
+    slots: HashMap<usize, usize>,
+}
+
+impl Roster {
+    pub fn new() -> Roster {
+        Self::default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Player> {
+        self.players.iter()
+    }
+
+    /// The player that had this `userid` at any point.
+    pub fn by_user_id(&self, user_id: i32) -> Option<&Player> {
+        self.user_ids.get(&user_id).map(|&i| &self.players[i])
+    }
+
+    /// The player with this Steam account. Bots have none, so can't be found this way.
+    pub fn by_steam_id(&self, steam_id: SteamId) -> Option<&Player> {
+        if steam_id.is_bot() {
+            return None;
+        }
+        self.players.iter().find(|p| p.steam_id == Some(steam_id))
+    }
+
+    /// The player currently in a `userinfo` slot.
+    pub fn by_slot(&self, slot: usize) -> Option<&Player> {
+        self.slots.get(&slot).map(|&i| &self.players[i])
+    }
+
+    /// The player currently controlling an entity.
+    pub fn by_entity(&self, entity_index: usize) -> Option<&Player> {
+        self.by_slot(entity_index.checked_sub(1)?)
+    }
+
+    /// Update a slot with its `userinfo` entry. `None` means the slot was emptied.
+    pub fn update(&mut self, slot: usize, info: Option<&PlayerInfo>, tick: u32) {
+        let Some(info) = info else {
+            if let Some(index) = self.slots.remove(&slot) {
+                self.players[index].disconnected_tick.get_or_insert(tick);
+            }
+            return;
+        };
+        let index = self.find_or_insert(slot, info.user_id, Some(info.steam_id), tick);
+        let player = &mut self.players[index];
+        player.steam_id = Some(info.steam_id);
+        player.is_bot = info.is_fake_player;
+        player.is_hltv = info.is_hltv;
+        player.rename(&info.name, tick);
+    }
+
+    /// Update the roster with a game event. Events about other things are ignored.
+    pub fn handle_event(&mut self, event: &DemoEvent) {
+        let tick = event.tick;
+        match &event.event {
+            Event::PlayerConnect(connect) => {
+                let slot = connect.index.max(0) as usize;
+                let steam_id = connect.networkid.parse().ok();
+                let index = self.find_or_insert(slot, connect.userid, steam_id, tick);
+                let player = &mut self.players[index];
+                player.is_bot = connect.networkid == "BOT";
+                if player.steam_id.is_none() {
+                    player.steam_id = steam_id;
+                }
+                player.rename(&connect.name, tick);
+            }
+            Event::PlayerDisconnect(disconnect) => {
+                if let Some(&index) = self.user_ids.get(&disconnect.userid) {
+                    let player = &mut self.players[index];
+                    player.disconnected_tick.get_or_insert(tick);
+                    if self.slots.get(&player.slot) == Some(&index) {
+                        self.slots.remove(&player.slot);
+                    }
+                }
+            }
+            Event::PlayerTeam(team) if !team.disconnect => {
+                if let Some(&index) = self.user_ids.get(&team.userid) {
+                    let player = &mut self.players[index];
+                    player.is_bot |= team.isbot;
+                    if player.team != team.team || player.teams.is_empty() {
+                        player.team = team.team;
+                        player.teams.push((tick, team.team));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Find the player for a connection, by `userid` or, for a reconnecting
+    /// player, by Steam id. Adds a new player if neither is known.
+    fn find_or_insert(
+        &mut self,
+        slot: usize,
+        user_id: i32,
+        steam_id: Option<SteamId>,
+        tick: u32,
+    ) -> usize {
+        let known = self.user_ids.get(&user_id).copied().or_else(|| {
+            let steam_id = steam_id.filter(|id| !id.is_bot())?;
+            self.players
+                .iter()
+                .position(|p| p.steam_id == Some(steam_id))
+        });
+        let index = match known {
+            Some(index) => {
+                let player = &mut self.players[index];
+                if player.user_id != user_id || !player.is_connected() {
+                    player.connected_tick = tick;
+                    player.disconnected_tick = None;
+                    player.team = 0;
+                }
+                player.user_id = user_id;
+                player.slot = slot;
+                index
+            }
+            None => {
+                self.players.push(Player::new(slot, user_id, tick));
+                self.players.len() - 1
+            }
+        };
+        self.user_ids.insert(user_id, index);
+        self.slots.insert(slot, index);
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{PlayerConnect, PlayerDisconnect, PlayerTeam};
+
+    const ALICE: SteamId = SteamId::Player(76561197960287930);
+
+    fn info(user_id: i32, steam_id: SteamId, name: &str) -> PlayerInfo {
+        PlayerInfo {
+            version: 0,
+            xuid: steam_id.steam_id64().unwrap_or_default() as i64,
+            steam_id,
+            name: name.to_string(),
+            user_id,
+            guid: String::new(),
+            friends_id: 0,
+            friends_name: String::new(),
+            is_fake_player: steam_id.is_bot(),
+            is_hltv: false,
+            custom_files: [0; 4],
+            files_downloaded: 0,
+        }
+    }
+
+    fn event(tick: u32, event: Event) -> DemoEvent {
+        DemoEvent { tick, event }
+    }
+
+    fn disconnect(tick: u32, userid: i32) -> DemoEvent {
+        event(
+            tick,
+            Event::PlayerDisconnect(PlayerDisconnect {
+                userid,
+                reason: String::new(),
+                name: String::new(),
+                networkid: String::new(),
+            }),
+        )
+    }
+
+    fn team(tick: u32, userid: i32, team: i32, disconnect: bool) -> DemoEvent {
+        event(
+            tick,
+            Event::PlayerTeam(PlayerTeam {
+                userid,
+                team,
+                oldteam: 0,
+                disconnect,
+                isbot: false,
+                silent: false,
+            }),
+        )
+    }
+
+    fn connect(tick: u32, slot: i32, userid: i32, networkid: &str, name: &str) -> DemoEvent {
+        event(
+            tick,
+            Event::PlayerConnect(PlayerConnect {
+                name: name.to_string(),
+                index: slot,
+                userid,
+                networkid: networkid.to_string(),
+            }),
+        )
+    }
+
+    #[test]
+    fn reconnect_by_steam_id() {
+        let mut roster = Roster::new();
+        roster.update(2, Some(&info(10, ALICE, "alice")), 100);
+        roster.handle_event(&team(110, 10, TEAM_T, false));
+        roster.handle_event(&disconnect(200, 10));
+        assert!(roster.by_slot(2).is_none());
+        assert_eq!(roster.by_user_id(10).unwrap().disconnected_tick, Some(200));
+
+        // A new userid and slot, but the same account.
+        roster.update(4, Some(&info(15, ALICE, "alice2")), 300);
+        assert_eq!(roster.iter().count(), 1);
+        let player = roster.by_entity(5).unwrap();
+        assert_eq!(player.user_id, 15);
+        assert_eq!(player.slot, 4);
+        assert_eq!(player.connected_tick, 300);
+        assert!(player.is_connected());
+        assert_eq!(player.team, 0);
+        assert_eq!(
+            player.names,
+            [(100, "alice".to_string()), (300, "alice2".to_string())]
+        );
+        // Events from the old connection still find the player.
+        assert_eq!(roster.by_user_id(10).unwrap().user_id, 15);
+        assert_eq!(roster.by_steam_id(ALICE).unwrap().user_id, 15);
+    }
+
+    #[test]
+    fn emptied_slot() {
+        let mut roster = Roster::new();
+        roster.update(2, Some(&info(10, ALICE, "alice")), 100);
+        roster.update(2, None, 150);
+        assert!(roster.by_slot(2).is_none());
+        assert_eq!(roster.by_user_id(10).unwrap().disconnected_tick, Some(150));
+        // The disconnect event comes later, and doesn't move the tick.
+        roster.handle_event(&disconnect(160, 10));
+        assert_eq!(roster.by_user_id(10).unwrap().disconnected_tick, Some(150));
+    }
+
+    #[test]
+    fn team_history() {
+        let mut roster = Roster::new();
+        roster.update(2, Some(&info(10, ALICE, "alice")), 100);
+        roster.handle_event(&team(110, 10, TEAM_T, false));
+        roster.handle_event(&team(120, 10, TEAM_T, false));
+        roster.handle_event(&team(500, 10, TEAM_CT, false));
+        // Sent as the player leaves, and not a team change.
+        roster.handle_event(&team(900, 10, 0, true));
+        let player = roster.by_user_id(10).unwrap();
+        assert_eq!(player.team, TEAM_CT);
+        assert_eq!(player.teams, [(110, TEAM_T), (500, TEAM_CT)]);
+    }
+
+    #[test]
+    fn bots_keep_their_identity() {
+        let mut roster = Roster::new();
+        roster.handle_event(&connect(50, 5, 20, "BOT", "Joe"));
+        roster.update(5, Some(&info(20, SteamId::Bot, "Joe")), 50);
+        roster.handle_event(&connect(60, 6, 21, "BOT", "Ann"));
+        roster.update(6, Some(&info(21, SteamId::Bot, "Ann")), 60);
+
+        // Bots share SteamId::Bot, which must not merge them.
+        assert_eq!(roster.iter().count(), 2);
+        assert!(roster.by_steam_id(SteamId::Bot).is_none());
+        roster.handle_event(&team(70, 20, TEAM_CT, false));
+        let joe = roster.by_user_id(20).unwrap();
+        assert!(joe.is_bot);
+        assert_eq!(joe.name, "Joe");
+        assert_eq!(joe.steam_id, Some(SteamId::Bot));
+        assert_eq!(joe.team, TEAM_CT);
+        assert_eq!(roster.by_user_id(21).unwrap().name, "Ann");
+    }
+}
@@ -0,0 +1,490 @@
+//! Rounds, from the round events and the game rules.
+//! Feed a [`RoundTracker`] every event, and the [`GameState`] after every frame.
+//!
+//! Round numbers count every round the tracker sees, warmup and knife rounds
+//! included, until [`RoundTracker::finish`] drops the ones the
+//! [`RoundOptions`] exclude and numbers the rest from 1 again.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::entities::Entity;
+use crate::events::{DemoEvent, Event};
+use crate::game_state::GameState;
+use crate::props::PropValue;
+use crate::roster::{TEAM_CT, TEAM_T};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Round {
+    /// Counted from 1.
+    pub number: u32,
+    /// `None` for a round the demo starts in the middle of.
+    pub start_tick: Option<u32>,
+    pub freeze_end_tick: Option<u32>,
+    pub end_tick: Option<u32>,
+    /// The winning team, 2 for T and 3 for CT.
+    pub winner: Option<i32>,
+    /// The engine's `CSRoundEndReason`.
+    pub reason: Option<i32>,
+    /// The score of the team on each side once the round is over.
+    pub score_ct: u32,
+    pub score_t: u32,
+    pub is_warmup: bool,
+    /// Every kill was with a knife, and nobody bought anything.
+    pub is_knife: bool,
+}
+
+impl Round {
+    fn new(number: u32, start_tick: Option<u32>) -> Round {
+        Round {
+            number,
+            start_tick,
+            freeze_end_tick: None,
+            end_tick: None,
+            winner: None,
+            reason: None,
+            score_ct: 0,
+            score_t: 0,
+            is_warmup: false,
+            is_knife: false,
+        }
+    }
+}
+
+/// Which rounds [`RoundTracker::finish`] leaves out.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoundOptions {
+    pub exclude_warmup: bool,
+    pub exclude_knife: bool,
+}
+
+/// What happened in the open round, to tell if it was a knife round.
+#[derive(Debug, Clone, Copy, Default)]
+struct Activity {
+    kills: u32,
+    knife_kills: u32,
+    purchases: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoundTracker {
+    rounds: Vec<Round>,
+    /// Whether the last round is still going, or hasn't been scored yet.
+    open: bool,
+    activity: Activity,
+    /// Rounds won by each side, for demos without team entities.
+    wins_t: u32,
+    wins_ct: u32,
+    in_freeze: bool,
+    rules: GameRulesReader,
+}
+
+impl RoundTracker {
+    pub fn new() -> RoundTracker {
+        RoundTracker::default()
+    }
+
+    /// The number of the round being played, or the last one. Zero before the first.
+    pub fn current(&self) -> u32 {
+        self.rounds.last().map_or(0, |r| r.number)
+    }
+
+    /// Every round so far, numbered as they were seen.
+    pub fn rounds(&self) -> &[Round] {
+        &self.rounds
+    }
+
+    pub fn handle_event(&mut self, event: &DemoEvent, state: &GameState) {
+        let tick = event.tick;
+        if self.rounds.is_empty() && is_in_round(&event.event) {
+            // The demo started mid round, so its first round has no start.
+            self.open_round();
+        }
+        match &event.event {
+            Event::RoundStart(_) => {
+                self.close(None);
+                let rules = self.rules.read(state);
+                if rules.is_some_and(|r| r.total_rounds_played == 0) {
+                    // A restart, at the end of warmup or after a knife round.
+                    self.wins_t = 0;
+                    self.wins_ct = 0;
+                }
+                let mut round = Round::new(self.current() + 1, Some(tick));
+                round.is_warmup = rules.is_some_and(|r| r.warmup);
+                self.rounds.push(round);
+                self.open = true;
+                self.activity = Activity::default();
+            }
+            Event::RoundFreezeEnd => {
+                if let Some(round) = self.open_round() {
+                    round.freeze_end_tick.get_or_insert(tick);
+                }
+            }
+            Event::RoundEnd(end) => {
+                let round = self.open_round();
+                if let Some(round) = round.filter(|r| r.end_tick.is_none()) {
+                    round.end_tick = Some(tick);
+                    round.winner = Some(end.winner);
+                    round.reason = Some(end.reason);
+                    match end.winner {
+                        TEAM_T => self.wins_t += 1,
+                        TEAM_CT => self.wins_ct += 1,
+                        _ => {}
+                    }
+                }
+            }
+            Event::RoundOfficiallyEnded => self.close(Some(state)),
+            Event::PlayerDeath(death) => {
+                self.activity.kills += 1;
+                if is_knife(&death.weapon) {
+                    self.activity.knife_kills += 1;
+                }
+            }
+            Event::ItemPurchase(_) => self.activity.purchases += 1,
+            _ => {}
+        }
+    }
+
+    /// Picks up what the events don't say: warmup, and the end of a freeze
+    /// time in demos without `round_freeze_end`.
+    pub fn update(&mut self, state: &GameState, tick: u32) {
+        let Some(rules) = self.rules.read(state) else {
+            return;
+        };
+        let freeze_ended = self.in_freeze && !rules.freeze;
+        self.in_freeze = rules.freeze;
+        if !self.open {
+            return;
+        }
+        if let Some(round) = self.rounds.last_mut() {
+            round.is_warmup |= rules.warmup;
+            if freeze_ended {
+                round.freeze_end_tick.get_or_insert(tick);
+            }
+        }
+    }
+
+    /// The rounds left after `options`, numbered from 1, and the new number
+    /// of each old one that was kept.
+    pub fn finish(
+        mut self,
+        state: &GameState,
+        options: RoundOptions,
+    ) -> (Vec<Round>, HashMap<u32, u32>) {
+        self.close(Some(state));
+        let mut numbers = HashMap::new();
+        let rounds = self
+            .rounds
+            .into_iter()
+            .filter(|r| !(options.exclude_warmup && r.is_warmup))
+            .filter(|r| !(options.exclude_knife && r.is_knife))
+            .zip(1..)
+            .map(|(mut round, number)| {
+                numbers.insert(round.number, number);
+                round.number = number;
+                round
+            })
+            .collect();
+        (rounds, numbers)
+    }
+
+    /// The open round, or a new one for a demo that starts mid round.
+    fn open_round(&mut self) -> Option<&mut Round> {
+        if !self.open {
+            let number = self.current() + 1;
+            self.rounds.push(Round::new(number, None));
+            self.open = true;
+        }
+        self.rounds.last_mut()
+    }
+
+    /// Score the open round. The team entities know the real score,
+    /// halftime included, but they're only up to date once the round has
+    /// officially ended, so a round closed by the next one starting
+    /// (`state` is `None`) keeps the wins counted per side.
+    fn close(&mut self, state: Option<&GameState>) {
+        if !self.open {
+            return;
+        }
+        self.open = false;
+        let activity = std::mem::take(&mut self.activity);
+        let scores = state.and_then(team_scores);
+        let Some(round) = self.rounds.last_mut() else {
+            return;
+        };
+        (round.score_ct, round.score_t) = scores.unwrap_or((self.wins_ct, self.wins_t));
+        round.is_knife =
+            activity.kills > 0 && activity.knife_kills == activity.kills && activity.purchases == 0;
+    }
+}
+
+/// Events that only happen while a round is being played.
+fn is_in_round(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::PlayerDeath(_)
+            | Event::PlayerHurt(_)
+            | Event::ItemPurchase(_)
+            | Event::HegrenadeDetonate(_)
+            | Event::FlashbangDetonate(_)
+            | Event::SmokegrenadeDetonate(_)
+            | Event::InfernoStartburn(_)
+    )
+}
+
+fn is_knife(weapon: &str) -> bool {
+    weapon.starts_with("knife") || weapon == "bayonet"
+}
+
+/// `GAMEPHASE_PLAYING_FIRST_HALF`, a [`GameRules::game_phase`].
+pub(crate) const GAME_PHASE_FIRST_HALF: i32 = 2;
+/// `GAMEPHASE_PLAYING_SECOND_HALF`, a [`GameRules::game_phase`].
+pub(crate) const GAME_PHASE_SECOND_HALF: i32 = 3;
+/// `GAMEPHASE_HALFTIME`, a [`GameRules::game_phase`].
+pub(crate) const GAME_PHASE_HALFTIME: i32 = 4;
+
+/// The props of the `CCSGameRulesProxy` entity we care about.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct GameRules {
+    pub(crate) total_rounds_played: i32,
+    pub(crate) warmup: bool,
+    pub(crate) freeze: bool,
+    /// The engine's `GamePhase`, which tells the halves apart.
+    pub(crate) game_phase: i32,
+    /// Zero in regulation, and the number of the overtime after.
+    pub(crate) overtime_playing: i32,
+}
+
+/// The paths of the [`GameRules`] props, in the order of `GameRulesProxy::fields`.
+const GAME_RULES_PROPS: [&str; 5] = [
+    "cs_gamerules_data.m_totalRoundsPlayed",
+    "cs_gamerules_data.m_bWarmupPeriod",
+    "cs_gamerules_data.m_bFreezePeriod",
+    "cs_gamerules_data.m_gamePhase",
+    "cs_gamerules_data.m_nOvertimePlaying",
+];
+
+/// Where the `CCSGameRulesProxy` entity and its props are.
+#[derive(Debug, Clone, Copy)]
+struct GameRulesProxy {
+    entity_index: usize,
+    class_id: usize,
+    /// The field index of each of [`GAME_RULES_PROPS`].
+    fields: [Option<usize>; 5],
+}
+
+impl GameRulesProxy {
+    fn find(state: &GameState) -> Option<GameRulesProxy> {
+        let data_table = state.data_table.as_ref()?;
+        let (entity, class) = state.entities.iter().find_map(|entity: &Entity| {
+            let class = data_table.server_classes.get(entity.class_id)?;
+            (class.name == "CCSGameRulesProxy").then_some((entity, class))
+        })?;
+        Some(GameRulesProxy {
+            entity_index: entity.index,
+            class_id: entity.class_id,
+            fields: GAME_RULES_PROPS.map(|path| class.prop_index(path)),
+        })
+    }
+}
+
+/// Reads the [`GameRules`], looking for the proxy entity only until it is found.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GameRulesReader {
+    proxy: Option<GameRulesProxy>,
+}
+
+impl GameRulesReader {
+    pub(crate) fn read(&mut self, state: &GameState) -> Option<GameRules> {
+        let cached = self.proxy.filter(|proxy| {
+            let entity = state.entities.get(proxy.entity_index);
+            entity.is_some_and(|e| e.class_id == proxy.class_id)
+        });
+        let proxy = match cached {
+            Some(proxy) => proxy,
+            None => *self.proxy.insert(GameRulesProxy::find(state)?),
+        };
+        let entity = state.entities.get(proxy.entity_index)?;
+        let [total_rounds_played, warmup, freeze, game_phase, overtime_playing] =
+            proxy.fields.map(|field| {
+                field
+                    .and_then(|f| entity.props.get(f)?.as_ref())
+                    .and_then(PropValue::as_int)
+                    .unwrap_or_default()
+            });
+        Some(GameRules {
+            total_rounds_played,
+            warmup: warmup != 0,
+            freeze: freeze != 0,
+            game_phase,
+            overtime_playing,
+        })
+    }
+}
+
+/// The `(ct, t)` scores from the `CCSTeam` entities.
+fn team_scores(state: &GameState) -> Option<(u32, u32)> {
+    let data_table = state.data_table.as_ref()?;
+    let (mut ct, mut t) = (None, None);
+    for entity in state.entities.iter() {
+        let Some(class) = data_table.server_classes.get(entity.class_id) else {
+            continue;
+        };
+        if class.name != "CCSTeam" {
+            continue;
+        }
+        let int = |path: &str| entity.prop(class, path).and_then(PropValue::as_int);
+        let score = int("m_scoreTotal").and_then(|s| u32::try_from(s).ok());
+        match int("m_iTeamNum") {
+            Some(TEAM_CT) => ct = score,
+            Some(TEAM_T) => t = score,
+            _ => {}
+        }
+    }
+    Some((ct?, t?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{ItemPurchase, PlayerDeath, RoundEnd, RoundStart};
+
+    fn event(tick: u32, event: Event) -> DemoEvent {
+        DemoEvent { tick, event }
+    }
+
+    fn kill(tick: u32, weapon: &str) -> DemoEvent {
+        event(
+            tick,
+            Event::PlayerDeath(PlayerDeath {
+                userid: 2,
+                attacker: 3,
+                assister: 0,
+                assistedflash: false,
+                weapon: weapon.to_string(),
+                headshot: false,
+                penetrated: 0,
+                noscope: false,
+                thrusmoke: false,
+                attackerblind: false,
+                distance: 0.0,
+            }),
+        )
+    }
+
+    fn purchase(tick: u32) -> DemoEvent {
+        event(
+            tick,
+            Event::ItemPurchase(ItemPurchase {
+                userid: 3,
+                team: TEAM_CT,
+                weapon: "weapon_p250".to_string(),
+            }),
+        )
+    }
+
+    /// Plays a round from `start` for each list of events, won by the CTs.
+    fn tracker(rounds: &[Vec<DemoEvent>]) -> RoundTracker {
+        let state = GameState::default();
+        let mut tracker = RoundTracker::new();
+        for (i, events) in rounds.iter().enumerate() {
+            let start = i as u32 * 100;
+            let start_event = RoundStart {
+                timelimit: 115,
+                fraglimit: 0,
+                objective: String::new(),
+            };
+            tracker.handle_event(&event(start, Event::RoundStart(start_event)), &state);
+            tracker.handle_event(&event(start + 10, Event::RoundFreezeEnd), &state);
+            events.iter().for_each(|e| tracker.handle_event(e, &state));
+            let end = RoundEnd {
+                winner: TEAM_CT,
+                reason: 8,
+                message: String::new(),
+                player_count: 10,
+            };
+            tracker.handle_event(&event(start + 80, Event::RoundEnd(end)), &state);
+            tracker.handle_event(&event(start + 90, Event::RoundOfficiallyEnded), &state);
+        }
+        tracker
+    }
+
+    /// A warmup round, a knife round, and two real ones, the last with a
+    /// knife kill after a purchase.
+    fn season() -> RoundTracker {
+        let mut tracker = tracker(&[
+            vec![kill(20, "ak47")],
+            vec![kill(120, "knife"), kill(130, "bayonet")],
+            vec![kill(220, "ak47"), kill(230, "knife_t")],
+            vec![purchase(305), kill(320, "knife")],
+        ]);
+        // Warmup comes from the game rules, which this state doesn't have.
+        tracker.rounds[0].is_warmup = true;
+        tracker
+    }
+
+    fn numbers(rounds: &[Round]) -> Vec<(u32, Option<u32>)> {
+        rounds.iter().map(|r| (r.number, r.start_tick)).collect()
+    }
+
+    #[test]
+    fn knife_rounds() {
+        let tracker = season();
+        let knife: Vec<bool> = tracker.rounds().iter().map(|r| r.is_knife).collect();
+        assert_eq!(knife, [false, true, false, false]);
+        let scores: Vec<(u32, u32)> = tracker
+            .rounds()
+            .iter()
+            .map(|r| (r.score_ct, r.score_t))
+            .collect();
+        assert_eq!(scores, [(1, 0), (2, 0), (3, 0), (4, 0)]);
+    }
+
+    #[test]
+    fn finish_keeps_everything_by_default() {
+        let state = GameState::default();
+        let (rounds, map) = season().finish(&state, RoundOptions::default());
+        assert_eq!(
+            numbers(&rounds),
+            [(1, Some(0)), (2, Some(100)), (3, Some(200)), (4, Some(300))]
+        );
+        assert_eq!(map, HashMap::from([(1, 1), (2, 2), (3, 3), (4, 4)]));
+    }
+
+    #[test]
+    fn finish_excludes_and_renumbers() {
+        let state = GameState::default();
+        let warmup = RoundOptions {
+            exclude_warmup: true,
+            exclude_knife: false,
+        };
+        let (rounds, map) = season().finish(&state, warmup);
+        assert_eq!(
+            numbers(&rounds),
+            [(1, Some(100)), (2, Some(200)), (3, Some(300))]
+        );
+        assert_eq!(map, HashMap::from([(2, 1), (3, 2), (4, 3)]));
+
+        let both = RoundOptions {
+            exclude_warmup: true,
+            exclude_knife: true,
+        };
+        let (rounds, map) = season().finish(&state, both);
+        assert_eq!(numbers(&rounds), [(1, Some(200)), (2, Some(300))]);
+        assert_eq!(map, HashMap::from([(3, 1), (4, 2)]));
+        assert!(!map.contains_key(&1) && !map.contains_key(&2));
+    }
+
+    #[test]
+    fn finish_closes_the_open_round() {
+        let state = GameState::default();
+        let mut tracker = RoundTracker::new();
+        // The demo starts mid round, and stops before it ends.
+        tracker.handle_event(&kill(150, "knife"), &state);
+        let (rounds, _) = tracker.finish(&state, RoundOptions::default());
+        assert_eq!(numbers(&rounds), [(1, None)]);
+        assert!(rounds[0].is_knife);
+        assert_eq!(rounds[0].end_tick, None);
+    }
+}
@@ -0,0 +1,656 @@
+//! The end of match scoreboard, one row per player, with the stats sites
+//! like HLTV show. Feed a [`ScoreboardBuilder`] every event along with the
+//! round it happened in, and finish it with the rounds that count.
+//!
+//! Kills, assists and damage only count against enemies. Damage is capped at
+//! the health the victim had left, so overkill doesn't inflate ADR.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::events::{DemoEvent, Event};
+use crate::game_state::GameState;
+use crate::positions::class_name;
+use crate::props::PropValue;
+use crate::roster::{TEAM_CT, TEAM_T};
+use crate::rounds::Round;
+use crate::steam_id::SteamId;
+
+/// A death is traded if the killer dies to the victim's team this soon after.
+const TRADE_SECONDS: f32 = 5.0;
+/// For demos without server info.
+const DEFAULT_TICK_INTERVAL: f32 = 1.0 / 64.0;
+/// Grenade damage, as `player_hurt` names it.
+const UTILITY: [&str; 4] = ["hegrenade", "inferno", "molotov", "incgrenade"];
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScoreboardRow {
+    pub user_id: i32,
+    pub steam_id: Option<SteamId>,
+    pub name: String,
+    /// The player's last team.
+    pub team: i32,
+    /// Rounds the player was on a team for.
+    pub rounds: u32,
+    pub kills: u32,
+    pub deaths: u32,
+    pub assists: u32,
+    pub headshot_kills: u32,
+    /// Percent of kills that were headshots.
+    pub headshot_pct: f32,
+    pub damage: i32,
+    /// Average damage per round.
+    pub adr: f32,
+    /// Percent of rounds with a kill, assist, survival or trade.
+    pub kast: f32,
+    /// The first kill of a round.
+    pub first_kills: u32,
+    pub first_deaths: u32,
+    pub rounds_2k: u32,
+    pub rounds_3k: u32,
+    pub rounds_4k: u32,
+    pub rounds_5k: u32,
+    /// Rounds the player was the last alive on their team, against at least one enemy.
+    pub clutches: u32,
+    pub clutches_won: u32,
+    pub utility_damage: i32,
+    pub enemies_flashed: u32,
+    /// Kills per round.
+    pub kpr: f32,
+    /// Deaths per round.
+    pub dpr: f32,
+    /// Assists per round.
+    pub apr: f32,
+    /// `2.13 * kpr + 0.42 * apr - 0.41`.
+    pub impact: f32,
+    /// The usual approximation of HLTV's Rating 2.0 from the columns above.
+    /// HLTV's own formula isn't public, so expect it to be off by a few hundredths.
+    pub rating: f32,
+}
+
+/// A `userid`, and the team the player was on at the time.
+type Who = (i32, i32);
+
+/// A `player_death`, with teams as they were at the time.
+#[derive(Debug, Clone)]
+struct Death {
+    tick: u32,
+    victim: Who,
+    attacker: Who,
+    assister: Who,
+    headshot: bool,
+}
+
+impl Death {
+    fn by_enemy(&self) -> bool {
+        is_enemy(self.victim, self.attacker)
+    }
+}
+
+/// Everything that happened in a round, by `userid` and team.
+#[derive(Debug, Clone, Default)]
+struct RoundLog {
+    /// Everyone on a team when the round started.
+    players: Vec<Who>,
+    deaths: Vec<Death>,
+    /// `(victim, attacker, damage, utility)`.
+    hits: Vec<(Who, Who, i32, bool)>,
+    /// `(victim, attacker)`.
+    blinds: Vec<(Who, Who)>,
+    /// Health left, to cap damage at.
+    health: HashMap<i32, i32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScoreboardBuilder {
+    rounds: HashMap<u32, RoundLog>,
+}
+
+/// One player's totals, before they become a row.
+#[derive(Debug, Clone, Default)]
+struct Totals {
+    rounds: u32,
+    kills: u32,
+    deaths: u32,
+    assists: u32,
+    headshot_kills: u32,
+    damage: i32,
+    kast_rounds: u32,
+    first_kills: u32,
+    first_deaths: u32,
+    multi_kills: [u32; 4],
+    clutches: u32,
+    clutches_won: u32,
+    utility_damage: i32,
+    enemies_flashed: u32,
+}
+
+impl ScoreboardBuilder {
+    pub fn new() -> ScoreboardBuilder {
+        ScoreboardBuilder::default()
+    }
+
+    /// `round` is the number from the [`RoundTracker`](crate::rounds::RoundTracker)
+    /// after it has seen the event.
+    pub fn handle_event(&mut self, event: &DemoEvent, state: &GameState, round: u32) {
+        let tick = event.tick;
+        let who = |user_id: i32| (user_id, team(state, user_id));
+        match &event.event {
+            Event::RoundStart(_) => {
+                let players = state
+                    .roster
+                    .iter()
+                    .filter(|p| p.is_connected() && is_playing(p.team))
+                    .map(|p| (p.user_id, p.team))
+                    .collect();
+                self.rounds.insert(
+                    round,
+                    RoundLog {
+                        players,
+                        ..RoundLog::default()
+                    },
+                );
+            }
+            Event::PlayerDeath(death) => {
+                let death = Death {
+                    tick,
+                    victim: who(death.userid),
+                    attacker: who(death.attacker),
+                    assister: who(death.assister),
+                    headshot: death.headshot,
+                };
+                self.rounds.entry(round).or_default().deaths.push(death);
+            }
+            Event::PlayerHurt(hurt) => {
+                let log = self.rounds.entry(round).or_default();
+                let left = log.health.insert(hurt.userid, hurt.health).unwrap_or(100);
+                let damage = hurt.dmg_health.min(left).max(0);
+                let utility = UTILITY.contains(&hurt.weapon.as_str());
+                log.hits
+                    .push((who(hurt.userid), who(hurt.attacker), damage, utility));
+            }
+            Event::PlayerBlind(blind) => {
+                let log = self.rounds.entry(round).or_default();
+                log.blinds.push((who(blind.userid), who(blind.attacker)));
+            }
+            _ => {}
+        }
+    }
+
+    /// A row for every player who played in `rounds`, the rounds that count.
+    /// `numbers` maps the tracker's round numbers to theirs, as
+    /// [`RoundTracker::finish`](crate::rounds::RoundTracker::finish) returns them.
+    pub fn finish(
+        self,
+        state: &GameState,
+        rounds: &[Round],
+        numbers: &HashMap<u32, u32>,
+    ) -> Vec<ScoreboardRow> {
+        let interval = state.tick_interval.unwrap_or(DEFAULT_TICK_INTERVAL);
+        let trade_ticks = (TRADE_SECONDS / interval) as u32;
+        // Reconnects change the `userid`, so players are keyed by their latest one.
+        let key = |user_id: i32| {
+            state
+                .roster
+                .by_user_id(user_id)
+                .map_or(user_id, |p| p.user_id)
+        };
+        let mut totals: HashMap<i32, Totals> = HashMap::new();
+
+        let mut logs: Vec<_> = self.rounds.into_iter().collect();
+        logs.sort_by_key(|&(number, _)| number);
+        for (number, log) in logs {
+            let Some(round) = numbers
+                .get(&number)
+                .and_then(|n| rounds.iter().find(|r| r.number == *n))
+            else {
+                continue;
+            };
+            score_round(round, &log, trade_ticks, &key, &mut totals);
+        }
+
+        let mut rows: Vec<_> = totals
+            .into_iter()
+            .filter(|(_, t)| t.rounds > 0)
+            .map(|(user_id, t)| row(state, user_id, &t))
+            .collect();
+        rows.sort_by_key(|r| r.user_id);
+        rows
+    }
+}
+
+fn score_round(
+    round: &Round,
+    log: &RoundLog,
+    trade_ticks: u32,
+    key: &impl Fn(i32) -> i32,
+    totals: &mut HashMap<i32, Totals>,
+) {
+    // Anyone who shows up on a team in the round played it,
+    // which covers a demo that starts after the round did.
+    let mut players: HashMap<i32, i32> = HashMap::new();
+    let involved = log
+        .deaths
+        .iter()
+        .flat_map(|d| [d.victim, d.attacker])
+        .chain(log.hits.iter().flat_map(|h| [h.0, h.1]));
+    for (user_id, team) in log.players.iter().copied().chain(involved) {
+        if user_id != 0 && is_playing(team) {
+            players.entry(key(user_id)).or_insert(team);
+        }
+    }
+
+    let mut alive: HashSet<i32> = players.keys().copied().collect();
+    let mut kills: HashMap<i32, u32> = HashMap::new();
+    let mut kast: HashSet<i32> = HashSet::new();
+    let mut clutchers: HashMap<i32, i32> = HashMap::new();
+    let mut first = true;
+    let ended = |tick: u32| round.end_tick.is_some_and(|end| tick > end);
+
+    for (i, death) in log.deaths.iter().enumerate() {
+        let victim = key(death.victim.0);
+        let attacker = key(death.attacker.0);
+        totals.entry(victim).or_default().deaths += 1;
+        if death.by_enemy() {
+            let t = totals.entry(attacker).or_default();
+            t.kills += 1;
+            t.headshot_kills += u32::from(death.headshot);
+            *kills.entry(attacker).or_default() += 1;
+            kast.insert(attacker);
+            if first {
+                t.first_kills += 1;
+                totals.entry(victim).or_default().first_deaths += 1;
+                first = false;
+            }
+            let traded = log.deaths[i + 1..].iter().any(|later| {
+                later.tick.saturating_sub(death.tick) <= trade_ticks
+                    && key(later.victim.0) == attacker
+                    && later.attacker.1 == death.victim.1
+            });
+            if traded {
+                kast.insert(victim);
+            }
+        }
+        if death.assister.0 != 0 && is_enemy(death.victim, death.assister) {
+            let assister = key(death.assister.0);
+            totals.entry(assister).or_default().assists += 1;
+            kast.insert(assister);
+        }
+
+        if ended(death.tick) {
+            continue;
+        }
+        alive.remove(&victim);
+        for team in [TEAM_T, TEAM_CT] {
+            let mut left = alive.iter().filter(|p| players[p] == team);
+            let enemies = alive.iter().filter(|p| players[p] != team).count();
+            if let (Some(&last), None) = (left.next(), left.next()) {
+                if enemies > 0 {
+                    clutchers.entry(team).or_insert(last);
+                }
+            }
+        }
+    }
+
+    for (&player, &team) in &players {
+        let t = totals.entry(player).or_default();
+        t.rounds += 1;
+        if alive.contains(&player) || kast.contains(&player) {
+            t.kast_rounds += 1;
+        }
+        let k = kills.get(&player).copied().unwrap_or(0);
+        if k >= 2 {
+            t.multi_kills[k.min(5) as usize - 2] += 1;
+        }
+        if clutchers.get(&team) == Some(&player) {
+            t.clutches += 1;
+            t.clutches_won += u32::from(round.winner == Some(team));
+        }
+    }
+
+    for &(victim, attacker, damage, utility) in &log.hits {
+        if is_enemy(victim, attacker) {
+            let t = totals.entry(key(attacker.0)).or_default();
+            t.damage += damage;
+            if utility {
+                t.utility_damage += damage;
+            }
+        }
+    }
+    for &(victim, attacker) in &log.blinds {
+        if is_enemy(victim, attacker) {
+            totals.entry(key(attacker.0)).or_default().enemies_flashed += 1;
+        }
+    }
+}
+
+fn row(state: &GameState, user_id: i32, t: &Totals) -> ScoreboardRow {
+    let player = state.roster.by_user_id(user_id);
+    let per_round = |n: f32| n / t.rounds as f32;
+    let kpr = per_round(t.kills as f32);
+    let dpr = per_round(t.deaths as f32);
+    let apr = per_round(t.assists as f32);
+    let adr = per_round(t.damage as f32);
+    let kast = 100.0 * per_round(t.kast_rounds as f32);
+    let impact = 2.13 * kpr + 0.42 * apr - 0.41;
+    ScoreboardRow {
+        user_id,
+        steam_id: player.and_then(|p| p.steam_id),
+        name: player.map(|p| p.name.clone()).unwrap_or_default(),
+        team: player.map_or(0, |p| p.team),
+        rounds: t.rounds,
+        kills: t.kills,
+        deaths: t.deaths,
+        assists: t.assists,
+        headshot_kills: t.headshot_kills,
+        headshot_pct: match t.kills {
+            0 => 0.0,
+            kills => 100.0 * t.headshot_kills as f32 / kills as f32,
+        },
+        damage: t.damage,
+        adr,
+        kast,
+        first_kills: t.first_kills,
+        first_deaths: t.first_deaths,
+        rounds_2k: t.multi_kills[0],
+        rounds_3k: t.multi_kills[1],
+        rounds_4k: t.multi_kills[2],
+        rounds_5k: t.multi_kills[3],
+        clutches: t.clutches,
+        clutches_won: t.clutches_won,
+        utility_damage: t.utility_damage,
+        enemies_flashed: t.enemies_flashed,
+        kpr,
+        dpr,
+        apr,
+        impact,
+        rating: 0.0073 * kast + 0.3591 * kpr - 0.5329 * dpr
+            + 0.2372 * impact
+            + 0.0032 * adr
+            + 0.1587,
+    }
+}
+
+fn is_playing(team: i32) -> bool {
+    team == TEAM_T || team == TEAM_CT
+}
+
+/// Two real players on opposing teams.
+fn is_enemy(victim: Who, attacker: Who) -> bool {
+    attacker.0 != 0
+        && attacker.0 != victim.0
+        && is_playing(attacker.1)
+        && is_playing(victim.1)
+        && attacker.1 != victim.1
+}
+
+/// The player's team from their entity, or from the roster if there isn't one.
+fn team(state: &GameState, user_id: i32) -> i32 {
+    let Some(player) = state.roster.by_user_id(user_id) else {
+        return 0;
+    };
+    entity_team(state, player.entity_index())
+        .filter(|&team| team != 0)
+        .unwrap_or(player.team)
+}
+
+fn entity_team(state: &GameState, entity_index: usize) -> Option<i32> {
+    let data_table = state.data_table.as_ref()?;
+    let entity = state.entities.get(entity_index)?;
+    if class_name(data_table, entity) != Some("CCSPlayer") {
+        return None;
+    }
+    let class = &data_table.server_classes[entity.class_id];
+    entity.prop(class, "m_iTeamNum").and_then(PropValue::as_int)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{
+        PlayerBlind, PlayerConnect, PlayerDeath, PlayerHurt, PlayerTeam, RoundStart,
+    };
+
+    const T: [i32; 5] = [1, 2, 3, 4, 5];
+    const CT: [i32; 5] = [6, 7, 8, 9, 10];
+
+    fn event(tick: u32, event: Event) -> DemoEvent {
+        DemoEvent { tick, event }
+    }
+
+    /// Five bots on each side, with `userid`s 1 to 5 on T and 6 to 10 on CT.
+    fn state() -> GameState {
+        let mut state = GameState {
+            tick_interval: Some(1.0 / 64.0),
+            ..GameState::default()
+        };
+        for (user_id, team) in T
+            .map(|u| (u, TEAM_T))
+            .into_iter()
+            .chain(CT.map(|u| (u, TEAM_CT)))
+        {
+            let connect = PlayerConnect {
+                name: format!("bot{user_id}"),
+                index: user_id - 1,
+                userid: user_id,
+                networkid: "BOT".to_string(),
+            };
+            state
+                .roster
+                .handle_event(&event(0, Event::PlayerConnect(connect)));
+            let join = PlayerTeam {
+                userid: user_id,
+                team,
+                oldteam: 0,
+                disconnect: false,
+                isbot: true,
+                silent: false,
+            };
+            state
+                .roster
+                .handle_event(&event(0, Event::PlayerTeam(join)));
+        }
+        state
+    }
+
+    fn start(tick: u32) -> DemoEvent {
+        let start = RoundStart {
+            timelimit: 115,
+            fraglimit: 0,
+            objective: String::new(),
+        };
+        event(tick, Event::RoundStart(start))
+    }
+
+    fn kill(tick: u32, victim: i32, attacker: i32, assister: i32, headshot: bool) -> DemoEvent {
+        event(
+            tick,
+            Event::PlayerDeath(PlayerDeath {
+                userid: victim,
+                attacker,
+                assister,
+                assistedflash: false,
+                weapon: "ak47".to_string(),
+                headshot,
+                penetrated: 0,
+                noscope: false,
+                thrusmoke: false,
+                attackerblind: false,
+                distance: 0.0,
+            }),
+        )
+    }
+
+    fn hurt(
+        tick: u32,
+        victim: i32,
+        attacker: i32,
+        health: i32,
+        damage: i32,
+        weapon: &str,
+    ) -> DemoEvent {
+        event(
+            tick,
+            Event::PlayerHurt(PlayerHurt {
+                userid: victim,
+                attacker,
+                health,
+                armor: 100,
+                weapon: weapon.to_string(),
+                dmg_health: damage,
+                dmg_armor: 0,
+                hitgroup: 0,
+            }),
+        )
+    }
+
+    fn blind(tick: u32, victim: i32, attacker: i32) -> DemoEvent {
+        event(
+            tick,
+            Event::PlayerBlind(PlayerBlind {
+                userid: victim,
+                attacker,
+                entityid: 0,
+                blind_duration: 2.0,
+            }),
+        )
+    }
+
+    fn round(number: u32, end_tick: u32, winner: i32) -> Round {
+        Round {
+            number,
+            start_tick: Some(end_tick - 1000),
+            end_tick: Some(end_tick),
+            winner: Some(winner),
+            ..Round::default()
+        }
+    }
+
+    /// Round 1: CT 7 gets four, and T 5 loses a 1v4.
+    /// Round 2: T 5 wins a 1v5 after CT 6 gets four.
+    fn scoreboard(rounds: &[Round], numbers: &HashMap<u32, u32>) -> HashMap<i32, ScoreboardRow> {
+        let state = state();
+        let events = [
+            (1, start(0)),
+            (1, hurt(50, 6, 1, 73, 27, "ak47")),
+            (1, hurt(100, 6, 1, 0, 150, "ak47")),
+            (1, kill(100, 6, 1, 0, true)),
+            (1, blind(150, 1, 9)),
+            (1, blind(150, 10, 9)),
+            (1, kill(200, 1, 7, 0, false)),
+            (1, hurt(250, 2, 8, 60, 40, "hegrenade")),
+            (1, hurt(260, 3, 2, 90, 10, "glock")),
+            (1, kill(300, 2, 7, 8, false)),
+            (1, kill(400, 3, 7, 0, true)),
+            (1, kill(500, 4, 7, 0, false)),
+            (1, kill(600, 5, 8, 0, false)),
+            (2, start(1000)),
+            (2, kill(1100, 1, 6, 0, false)),
+            (2, kill(1200, 2, 6, 0, false)),
+            (2, kill(1300, 3, 6, 0, false)),
+            (2, kill(1400, 4, 6, 0, false)),
+            (2, kill(1500, 6, 5, 0, true)),
+            (2, kill(1600, 7, 5, 0, false)),
+            (2, kill(1700, 8, 5, 0, false)),
+            (2, kill(1800, 9, 5, 0, false)),
+            (2, kill(1900, 10, 5, 0, false)),
+        ];
+        let mut builder = ScoreboardBuilder::new();
+        for (round, event) in &events {
+            builder.handle_event(event, &state, *round);
+        }
+        builder
+            .finish(&state, rounds, numbers)
+            .into_iter()
+            .map(|row| (row.user_id, row))
+            .collect()
+    }
+
+    fn both_rounds() -> HashMap<i32, ScoreboardRow> {
+        let rounds = [round(1, 1000, TEAM_CT), round(2, 2000, TEAM_T)];
+        scoreboard(&rounds, &HashMap::from([(1, 1), (2, 2)]))
+    }
+
+    #[test]
+    fn kills_and_multi_kills() {
+        let rows = both_rounds();
+        assert_eq!(rows.len(), 10);
+        assert!(rows.values().all(|r| r.rounds == 2));
+        let t5 = &rows[&5];
+        assert_eq!((t5.kills, t5.deaths, t5.rounds_5k), (5, 1, 1));
+        assert_eq!((t5.headshot_kills, t5.headshot_pct), (1, 20.0));
+        let ct7 = &rows[&7];
+        assert_eq!((ct7.kills, ct7.deaths, ct7.rounds_4k), (4, 1, 1));
+        let ct6 = &rows[&6];
+        assert_eq!((ct6.kills, ct6.rounds_4k, ct6.first_kills), (4, 1, 1));
+        assert_eq!(rows[&8].assists, 1);
+        // Round 1's first kill and death.
+        assert_eq!((rows[&1].first_kills, rows[&6].first_deaths), (1, 1));
+        assert_eq!(rows[&1].first_deaths, 1);
+        let multi = |r: &ScoreboardRow| r.rounds_2k + r.rounds_3k + r.rounds_4k + r.rounds_5k;
+        assert_eq!(rows.values().map(multi).sum::<u32>(), 3);
+    }
+
+    #[test]
+    fn kast_and_trades() {
+        let rows = both_rounds();
+        // Killed in round 1, then traded by CT 7 within the window.
+        assert_eq!(rows[&6].kast, 100.0);
+        // T 5 kills CT 6 at 1500, which trades CT 6's kills from tick 1180 on.
+        assert_eq!(rows[&4].kast, 50.0);
+        assert_eq!(rows[&3].kast, 50.0);
+        assert_eq!(rows[&2].kast, 50.0);
+        // T 1 got a kill in round 1, but round 2's death was avenged too late.
+        assert_eq!(rows[&1].kast, 50.0);
+        // Nothing in round 1, then kills and survival in round 2.
+        assert_eq!(rows[&5].kast, 50.0);
+        // A kill and survival in round 1, then an untraded death.
+        assert_eq!(rows[&8].kast, 50.0);
+    }
+
+    #[test]
+    fn clutches() {
+        let rows = both_rounds();
+        assert_eq!((rows[&5].clutches, rows[&5].clutches_won), (2, 1));
+        // The last CT alive in round 2, against T 5.
+        assert_eq!((rows[&10].clutches, rows[&10].clutches_won), (1, 0));
+        let total: u32 = rows.values().map(|r| r.clutches).sum();
+        assert_eq!(total, 3);
+    }
+
+    #[test]
+    fn damage() {
+        let rows = both_rounds();
+        // 27, then 150 capped at the 73 left.
+        assert_eq!((rows[&1].damage, rows[&1].adr), (100, 50.0));
+        assert_eq!((rows[&8].damage, rows[&8].utility_damage), (40, 40));
+        // Team damage doesn't count.
+        assert_eq!(rows[&2].damage, 0);
+        // Flashing a teammate doesn't either.
+        assert_eq!(rows[&9].enemies_flashed, 1);
+    }
+
+    #[test]
+    fn impact_and_rating() {
+        let rows = both_rounds();
+        let t1 = &rows[&1];
+        assert_eq!((t1.kpr, t1.dpr, t1.apr), (0.5, 1.0, 0.0));
+        assert!((t1.impact - 0.655).abs() < 1e-5);
+        // 0.0073 * 50 + 0.3591 * 0.5 - 0.5329 * 1 + 0.2372 * 0.655 + 0.0032 * 50 + 0.1587
+        assert!((t1.rating - 0.485716).abs() < 1e-5);
+    }
+
+    #[test]
+    fn only_counted_rounds() {
+        // Round 1 was warmup, so the tracker kept round 2 as round 1.
+        let rounds = [round(1, 2000, TEAM_T)];
+        let rows = scoreboard(&rounds, &HashMap::from([(2, 1)]));
+        assert!(rows.values().all(|r| r.rounds == 1));
+        assert_eq!(rows[&7].kills, 0);
+        assert_eq!(rows[&1].damage, 0);
+        assert_eq!((rows[&5].kills, rows[&5].clutches_won), (5, 1));
+        assert_eq!(rows[&1].name, "bot1");
+        assert_eq!(rows[&6].team, TEAM_CT);
+    }
+}
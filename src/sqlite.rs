@@ -0,0 +1,555 @@
+//! Writes demos into a SQLite database. Only built with the `sqlite` feature.
+//!
+//! Every demo is one row in `demo`, keyed by the SHA-256 of its file, and
+//! every other row points back at it. Rows about players point at
+//! `players`, and rows about a round at `rounds`, so one database can hold
+//! any number of demos.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use rusqlite::{params, Connection, Transaction};
+use sha2::{Digest, Sha256};
+
+use crate::demo::DemoHeader;
+use crate::error::Result;
+use crate::steam_id::SteamId;
+use crate::tables::Tables;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS demo (
+    id INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL UNIQUE,
+    demo_protocol INTEGER NOT NULL,
+    network_protocol INTEGER NOT NULL,
+    server_name TEXT NOT NULL,
+    client_name TEXT NOT NULL,
+    map_name TEXT NOT NULL,
+    game_directory TEXT NOT NULL,
+    playback_time REAL NOT NULL,
+    ticks INTEGER NOT NULL,
+    frames INTEGER NOT NULL,
+    sign_on_length INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS players (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    steam_id INTEGER,
+    name TEXT NOT NULL,
+    is_bot INTEGER NOT NULL,
+    is_hltv INTEGER NOT NULL,
+    team INTEGER NOT NULL,
+    connected_tick INTEGER NOT NULL,
+    disconnected_tick INTEGER
+);
+CREATE TABLE IF NOT EXISTS rounds (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    number INTEGER NOT NULL,
+    start_tick INTEGER,
+    freeze_end_tick INTEGER,
+    end_tick INTEGER,
+    winner INTEGER,
+    reason INTEGER,
+    score_ct INTEGER NOT NULL,
+    score_t INTEGER NOT NULL,
+    is_warmup INTEGER NOT NULL,
+    is_knife INTEGER NOT NULL,
+    UNIQUE (demo_id, number)
+);
+CREATE TABLE IF NOT EXISTS kills (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    round_id INTEGER REFERENCES rounds(id),
+    tick INTEGER NOT NULL,
+    victim_id INTEGER REFERENCES players(id),
+    victim_team INTEGER,
+    attacker_id INTEGER REFERENCES players(id),
+    attacker_team INTEGER,
+    assister_id INTEGER REFERENCES players(id),
+    weapon TEXT NOT NULL,
+    headshot INTEGER NOT NULL,
+    penetrated INTEGER NOT NULL,
+    noscope INTEGER NOT NULL,
+    thrusmoke INTEGER NOT NULL,
+    attackerblind INTEGER NOT NULL,
+    assistedflash INTEGER NOT NULL,
+    distance REAL NOT NULL,
+    victim_x REAL,
+    victim_y REAL,
+    victim_z REAL,
+    victim_pitch REAL,
+    victim_yaw REAL,
+    attacker_x REAL,
+    attacker_y REAL,
+    attacker_z REAL,
+    attacker_pitch REAL,
+    attacker_yaw REAL
+);
+CREATE TABLE IF NOT EXISTS damage (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    round_id INTEGER REFERENCES rounds(id),
+    tick INTEGER NOT NULL,
+    victim_id INTEGER REFERENCES players(id),
+    attacker_id INTEGER REFERENCES players(id),
+    weapon TEXT NOT NULL,
+    dmg_health INTEGER NOT NULL,
+    dmg_armor INTEGER NOT NULL,
+    health INTEGER NOT NULL,
+    armor INTEGER NOT NULL,
+    hitgroup INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS grenades (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    round_id INTEGER REFERENCES rounds(id),
+    tick INTEGER NOT NULL,
+    grenade TEXT NOT NULL,
+    player_id INTEGER REFERENCES players(id),
+    entity_id INTEGER NOT NULL,
+    x REAL NOT NULL,
+    y REAL NOT NULL,
+    z REAL NOT NULL
+);
+CREATE TABLE IF NOT EXISTS purchases (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    round_id INTEGER REFERENCES rounds(id),
+    tick INTEGER NOT NULL,
+    player_id INTEGER REFERENCES players(id),
+    team INTEGER NOT NULL,
+    weapon TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS positions (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    tick INTEGER NOT NULL,
+    player_id INTEGER REFERENCES players(id),
+    entity_index INTEGER NOT NULL,
+    x REAL NOT NULL,
+    y REAL NOT NULL,
+    z REAL NOT NULL,
+    pitch REAL NOT NULL,
+    yaw REAL NOT NULL,
+    velocity_x REAL NOT NULL,
+    velocity_y REAL NOT NULL,
+    velocity_z REAL NOT NULL,
+    health INTEGER NOT NULL,
+    armor INTEGER NOT NULL,
+    weapon TEXT,
+    team INTEGER NOT NULL,
+    is_alive INTEGER NOT NULL,
+    is_ducking INTEGER NOT NULL,
+    is_on_ground INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS positions_demo_tick ON positions (demo_id, tick);
+CREATE TABLE IF NOT EXISTS economy (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    round_id INTEGER REFERENCES rounds(id),
+    team INTEGER NOT NULL,
+    players INTEGER NOT NULL,
+    start_money INTEGER NOT NULL,
+    round_start_equipment_value INTEGER NOT NULL,
+    money INTEGER NOT NULL,
+    spent INTEGER NOT NULL,
+    equipment_value INTEGER NOT NULL,
+    buy TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS player_economy (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    round_id INTEGER REFERENCES rounds(id),
+    player_id INTEGER REFERENCES players(id),
+    team INTEGER NOT NULL,
+    start_money INTEGER NOT NULL,
+    round_start_equipment_value INTEGER NOT NULL,
+    money INTEGER NOT NULL,
+    spent INTEGER NOT NULL,
+    equipment_value INTEGER NOT NULL,
+    inventory TEXT NOT NULL,
+    purchases TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS scoreboard (
+    id INTEGER PRIMARY KEY,
+    demo_id INTEGER NOT NULL REFERENCES demo(id) ON DELETE CASCADE,
+    player_id INTEGER REFERENCES players(id),
+    rounds INTEGER NOT NULL,
+    kills INTEGER NOT NULL,
+    deaths INTEGER NOT NULL,
+    assists INTEGER NOT NULL,
+    headshot_kills INTEGER NOT NULL,
+    headshot_pct REAL NOT NULL,
+    damage INTEGER NOT NULL,
+    adr REAL NOT NULL,
+    kast REAL NOT NULL,
+    first_kills INTEGER NOT NULL,
+    first_deaths INTEGER NOT NULL,
+    rounds_2k INTEGER NOT NULL,
+    rounds_3k INTEGER NOT NULL,
+    rounds_4k INTEGER NOT NULL,
+    rounds_5k INTEGER NOT NULL,
+    clutches INTEGER NOT NULL,
+    clutches_won INTEGER NOT NULL,
+    utility_damage INTEGER NOT NULL,
+    enemies_flashed INTEGER NOT NULL,
+    kpr REAL NOT NULL,
+    dpr REAL NOT NULL,
+    apr REAL NOT NULL,
+    impact REAL NOT NULL,
+    rating REAL NOT NULL
+);
+";
+
+/// The SHA-256 of a demo file, as lowercase hex. Identifies the demo in the database.
+pub fn demo_hash(raw: &[u8]) -> String {
+    Sha256::digest(raw)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Write a demo into the database at `path`, creating it and its tables if needed.
+/// A demo that is already in the database (by `hash`) is replaced.
+pub fn write_sqlite(path: &Path, hash: &str, header: &DemoHeader, tables: &Tables) -> Result<()> {
+    let mut conn = Connection::open(path)?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    conn.execute_batch(SCHEMA)?;
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM demo WHERE hash = ?1", [hash])?;
+    tx.execute(
+        "INSERT INTO demo (hash, demo_protocol, network_protocol, server_name, client_name,
+            map_name, game_directory, playback_time, ticks, frames, sign_on_length)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            hash,
+            header.demo_protocol,
+            header.network_protocol,
+            header.server_name,
+            header.client_name,
+            header.map_name,
+            header.game_directory,
+            header.playback_time,
+            header.ticks,
+            header.frames,
+            header.sign_on_length,
+        ],
+    )?;
+    let demo_id = tx.last_insert_rowid();
+    let players = insert_players(&tx, demo_id, tables)?;
+    let rounds = insert_rounds(&tx, demo_id, tables)?;
+    insert_events(&tx, demo_id, tables, &players, &rounds)?;
+    insert_scoreboard(&tx, demo_id, tables, &players)?;
+    insert_economy(&tx, demo_id, tables, &players, &rounds)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Maps players in rows to their ids in `players`.
+/// Steam ids come first, as a player's `user_id` changes when they reconnect.
+#[derive(Default)]
+struct PlayerIds {
+    by_steam_id: HashMap<SteamId, i64>,
+    by_user_id: HashMap<i32, i64>,
+}
+
+impl PlayerIds {
+    fn get(&self, user_id: Option<i32>, steam_id: Option<SteamId>) -> Option<i64> {
+        steam_id
+            .filter(|id| !id.is_bot())
+            .and_then(|id| self.by_steam_id.get(&id))
+            .or_else(|| self.by_user_id.get(&user_id?))
+            .copied()
+    }
+}
+
+fn insert_players(tx: &Transaction, demo_id: i64, tables: &Tables) -> Result<PlayerIds> {
+    let mut ids = PlayerIds::default();
+    let mut insert = tx.prepare(
+        "INSERT INTO players (demo_id, user_id, steam_id, name, is_bot, is_hltv, team,
+            connected_tick, disconnected_tick)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    for p in &tables.players {
+        insert.execute(params![
+            demo_id,
+            p.user_id,
+            steam_id64(p.steam_id),
+            p.name,
+            p.is_bot,
+            p.is_hltv,
+            p.team,
+            p.connected_tick,
+            p.disconnected_tick,
+        ])?;
+        let id = tx.last_insert_rowid();
+        if let Some(steam_id) = p.steam_id.filter(|id| !id.is_bot()) {
+            ids.by_steam_id.insert(steam_id, id);
+        }
+        ids.by_user_id.insert(p.user_id, id);
+    }
+    Ok(ids)
+}
+
+/// Returns the id in `rounds` of each round, by number.
+fn insert_rounds(tx: &Transaction, demo_id: i64, tables: &Tables) -> Result<HashMap<u32, i64>> {
+    let mut ids = HashMap::new();
+    let mut insert = tx.prepare(
+        "INSERT INTO rounds (demo_id, number, start_tick, freeze_end_tick, end_tick, winner,
+            reason, score_ct, score_t, is_warmup, is_knife)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+    )?;
+    for r in &tables.rounds {
+        insert.execute(params![
+            demo_id,
+            r.number,
+            r.start_tick,
+            r.freeze_end_tick,
+            r.end_tick,
+            r.winner,
+            r.reason,
+            r.score_ct,
+            r.score_t,
+            r.is_warmup,
+            r.is_knife
+        ])?;
+        ids.insert(r.number, tx.last_insert_rowid());
+    }
+    Ok(ids)
+}
+
+fn insert_events(
+    tx: &Transaction,
+    demo_id: i64,
+    tables: &Tables,
+    players: &PlayerIds,
+    rounds: &HashMap<u32, i64>,
+) -> Result<()> {
+    let mut insert = tx.prepare(
+        "INSERT INTO kills (demo_id, round_id, tick, victim_id, victim_team, attacker_id,
+            attacker_team, assister_id, weapon, headshot, penetrated, noscope, thrusmoke,
+            attackerblind, assistedflash, distance, victim_x, victim_y, victim_z, victim_pitch,
+            victim_yaw, attacker_x, attacker_y, attacker_z, attacker_pitch, attacker_yaw)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+            ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26)",
+    )?;
+    for k in &tables.kills {
+        insert.execute(params![
+            demo_id,
+            rounds.get(&k.round),
+            k.tick,
+            players.get(k.victim_user_id, k.victim_steam_id),
+            k.victim_team,
+            players.get(k.attacker_user_id, k.attacker_steam_id),
+            k.attacker_team,
+            players.get(k.assister_user_id, k.assister_steam_id),
+            k.weapon,
+            k.headshot,
+            k.penetrated,
+            k.noscope,
+            k.thrusmoke,
+            k.attackerblind,
+            k.assistedflash,
+            k.distance,
+            k.victim_x,
+            k.victim_y,
+            k.victim_z,
+            k.victim_pitch,
+            k.victim_yaw,
+            k.attacker_x,
+            k.attacker_y,
+            k.attacker_z,
+            k.attacker_pitch,
+            k.attacker_yaw,
+        ])?;
+    }
+
+    let mut insert = tx.prepare(
+        "INSERT INTO damage (demo_id, round_id, tick, victim_id, attacker_id, weapon,
+            dmg_health, dmg_armor, health, armor, hitgroup)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+    )?;
+    for d in &tables.damage {
+        insert.execute(params![
+            demo_id,
+            rounds.get(&d.round),
+            d.tick,
+            players.get(d.victim_user_id, d.victim_steam_id),
+            players.get(d.attacker_user_id, d.attacker_steam_id),
+            d.weapon,
+            d.dmg_health,
+            d.dmg_armor,
+            d.health,
+            d.armor,
+            d.hitgroup,
+        ])?;
+    }
+
+    let mut insert = tx.prepare(
+        "INSERT INTO grenades (demo_id, round_id, tick, grenade, player_id, entity_id, x, y, z)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    for g in &tables.grenades {
+        insert.execute(params![
+            demo_id,
+            rounds.get(&g.round),
+            g.tick,
+            g.grenade,
+            players.get(g.user_id, g.steam_id),
+            g.entity_id,
+            g.x,
+            g.y,
+            g.z,
+        ])?;
+    }
+
+    let mut insert = tx.prepare(
+        "INSERT INTO purchases (demo_id, round_id, tick, player_id, team, weapon)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for p in &tables.purchases {
+        insert.execute(params![
+            demo_id,
+            rounds.get(&p.round),
+            p.tick,
+            players.get(p.user_id, p.steam_id),
+            p.team,
+            p.weapon,
+        ])?;
+    }
+
+    let mut insert = tx.prepare(
+        "INSERT INTO positions (demo_id, tick, player_id, entity_index, x, y, z, pitch, yaw,
+            velocity_x, velocity_y, velocity_z, health, armor, weapon, team,
+            is_alive, is_ducking, is_on_ground)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+    )?;
+    for p in &tables.positions {
+        insert.execute(params![
+            demo_id,
+            p.tick,
+            players.get(p.user_id, p.steam_id),
+            p.entity_index,
+            p.x,
+            p.y,
+            p.z,
+            p.pitch,
+            p.yaw,
+            p.velocity_x,
+            p.velocity_y,
+            p.velocity_z,
+            p.health,
+            p.armor,
+            p.weapon,
+            p.team,
+            p.is_alive,
+            p.is_ducking,
+            p.is_on_ground,
+        ])?;
+    }
+    Ok(())
+}
+
+fn insert_scoreboard(
+    tx: &Transaction,
+    demo_id: i64,
+    tables: &Tables,
+    players: &PlayerIds,
+) -> Result<()> {
+    let mut insert = tx.prepare(
+        "INSERT INTO scoreboard (demo_id, player_id, rounds, kills, deaths, assists,
+            headshot_kills, headshot_pct, damage, adr, kast, first_kills, first_deaths,
+            rounds_2k, rounds_3k, rounds_4k, rounds_5k, clutches, clutches_won,
+            utility_damage, enemies_flashed, kpr, dpr, apr, impact, rating)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+            ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26)",
+    )?;
+    for s in &tables.scoreboard {
+        insert.execute(params![
+            demo_id,
+            players.get(Some(s.user_id), s.steam_id),
+            s.rounds,
+            s.kills,
+            s.deaths,
+            s.assists,
+            s.headshot_kills,
+            s.headshot_pct,
+            s.damage,
+            s.adr,
+            s.kast,
+            s.first_kills,
+            s.first_deaths,
+            s.rounds_2k,
+            s.rounds_3k,
+            s.rounds_4k,
+            s.rounds_5k,
+            s.clutches,
+            s.clutches_won,
+            s.utility_damage,
+            s.enemies_flashed,
+            s.kpr,
+            s.dpr,
+            s.apr,
+            s.impact,
+            s.rating,
+        ])?;
+    }
+    Ok(())
+}
+
+fn insert_economy(
+    tx: &Transaction,
+    demo_id: i64,
+    tables: &Tables,
+    players: &PlayerIds,
+    rounds: &HashMap<u32, i64>,
+) -> Result<()> {
+    let mut insert = tx.prepare(
+        "INSERT INTO economy (demo_id, round_id, team, players, start_money,
+            round_start_equipment_value, money, spent, equipment_value, buy)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    )?;
+    for e in &tables.economy {
+        insert.execute(params![
+            demo_id,
+            rounds.get(&e.round),
+            e.team,
+            e.players,
+            e.start_money,
+            e.round_start_equipment_value,
+            e.money,
+            e.spent,
+            e.equipment_value,
+            e.buy.as_str(),
+        ])?;
+    }
+
+    let mut insert = tx.prepare(
+        "INSERT INTO player_economy (demo_id, round_id, player_id, team, start_money,
+            round_start_equipment_value, money, spent, equipment_value, inventory, purchases)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+    )?;
+    for p in &tables.player_economy {
+        insert.execute(params![
+            demo_id,
+            rounds.get(&p.round),
+            players.get(p.user_id, p.steam_id),
+            p.team,
+            p.start_money,
+            p.round_start_equipment_value,
+            p.money,
+            p.spent,
+            p.equipment_value,
+            p.inventory,
+            p.purchases,
+        ])?;
+    }
+    Ok(())
+}
+
+/// SQLite integers are signed, but every SteamID64 fits.
+fn steam_id64(steam_id: Option<SteamId>) -> Option<i64> {
+    Some(steam_id?.steam_id64()? as i64)
+}
@@ -0,0 +1,196 @@
+//! Steam account ids, in all the forms they are written in.
+//!
+//! A SteamID64 packs the universe, account type, instance and account id
+//! into one number. Every player we see is an individual account in the
+//! public universe, so the account id is all that really varies.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+
+use crate::error::{DemoError, Result};
+
+/// The SteamID64 of account id zero: public universe, individual account, desktop instance.
+const INDIVIDUAL_BASE: u64 = 0x0110_0001_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SteamId {
+    /// A player with a Steam account, by SteamID64.
+    Player(u64),
+    /// A bot or GOTV, which have no Steam account.
+    Bot,
+}
+
+impl SteamId {
+    pub fn from_steam_id64(steam_id64: u64) -> SteamId {
+        SteamId::Player(steam_id64)
+    }
+
+    pub fn from_account_id(account_id: u32) -> SteamId {
+        SteamId::Player(INDIVIDUAL_BASE + account_id as u64)
+    }
+
+    /// The id of the player in a `userinfo` entry.
+    /// Fake players get [`SteamId::Bot`], whatever their `xuid`.
+    pub fn from_xuid(xuid: i64, is_fake_player: bool) -> SteamId {
+        if is_fake_player || xuid == 0 {
+            SteamId::Bot
+        } else {
+            SteamId::Player(xuid as u64)
+        }
+    }
+
+    pub fn steam_id64(&self) -> Option<u64> {
+        match self {
+            SteamId::Player(id) => Some(*id),
+            SteamId::Bot => None,
+        }
+    }
+
+    /// The low 32 bits of the SteamID64, which is what Steam3 ids show.
+    pub fn account_id(&self) -> Option<u32> {
+        self.steam_id64().map(|id| id as u32)
+    }
+
+    pub fn is_bot(&self) -> bool {
+        *self == SteamId::Bot
+    }
+
+    /// Like `STEAM_1:1:12345`, or `BOT`. The universe is always 1, like in CS:GO.
+    pub fn to_steam2(&self) -> String {
+        match self.account_id() {
+            Some(account) => format!("STEAM_1:{}:{}", account & 1, account >> 1),
+            None => "BOT".to_string(),
+        }
+    }
+
+    /// Like `[U:1:24691]`, or `BOT`.
+    pub fn to_steam3(&self) -> String {
+        match self.account_id() {
+            Some(account) => format!("[U:1:{account}]"),
+            None => "BOT".to_string(),
+        }
+    }
+}
+
+/// Shows the SteamID64, or `BOT`.
+impl fmt::Display for SteamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SteamId::Player(id) => write!(f, "{id}"),
+            SteamId::Bot => f.write_str("BOT"),
+        }
+    }
+}
+
+/// Parses a SteamID64, a Steam2 id, a Steam3 id, an account id, or `BOT`.
+/// A plain number that fits in 32 bits is taken to be an account id,
+/// except for 0, which is the `xuid` of bots.
+impl FromStr for SteamId {
+    type Err = DemoError;
+
+    fn from_str(s: &str) -> Result<SteamId> {
+        let bad = || DemoError::BadSteamId(s.to_string());
+        if s == "BOT" {
+            return Ok(SteamId::Bot);
+        }
+        if let Some(rest) = s.strip_prefix("STEAM_") {
+            let mut parts = rest.split(':').map(|p| p.parse::<u32>().map_err(|_| bad()));
+            let (Some(universe), Some(y), Some(z), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(bad());
+            };
+            let (universe, y, z) = (universe?, y?, z?);
+            // Old games write the public universe as 0.
+            if universe > 1 || y > 1 || z > u32::MAX >> 1 {
+                return Err(bad());
+            }
+            return Ok(SteamId::from_account_id(z << 1 | y));
+        }
+        let steam3 = s.strip_prefix('[').and_then(|s| s.strip_suffix(']'));
+        if let Some(account) = steam3.unwrap_or(s).strip_prefix("U:1:") {
+            let account = account.parse().map_err(|_| bad())?;
+            return Ok(SteamId::from_account_id(account));
+        }
+        let id: u64 = s.parse().map_err(|_| bad())?;
+        match u32::try_from(id) {
+            Ok(0) => Ok(SteamId::from_xuid(0, false)),
+            Ok(account) => Ok(SteamId::from_account_id(account)),
+            Err(_) => Ok(SteamId::Player(id)),
+        }
+    }
+}
+
+/// As a string, since SteamID64s don't fit in a JSON number without losing precision.
+impl Serialize for SteamId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEAM_ID64: u64 = 76561197960287930;
+
+    fn parse(s: &str) -> SteamId {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let id = SteamId::from_steam_id64(STEAM_ID64);
+        assert_eq!(id.account_id(), Some(22202));
+        assert_eq!(id, SteamId::from_account_id(22202));
+        assert_eq!(id.to_steam2(), "STEAM_1:0:11101");
+        assert_eq!(id.to_steam3(), "[U:1:22202]");
+
+        assert_eq!(parse(&id.to_string()), id);
+        assert_eq!(parse(&id.to_steam2()), id);
+        assert_eq!(parse(&id.to_steam3()), id);
+        assert_eq!(parse("U:1:22202"), id);
+        assert_eq!(parse("22202"), id);
+        assert_eq!(parse("STEAM_0:0:11101"), id);
+
+        let odd = SteamId::from_account_id(12345);
+        assert_eq!(odd.to_steam2(), "STEAM_1:1:6172");
+        assert_eq!(parse(&odd.to_steam2()), odd);
+    }
+
+    #[test]
+    fn bots() {
+        assert_eq!(parse("BOT"), SteamId::Bot);
+        assert_eq!(parse(&SteamId::Bot.to_string()), SteamId::Bot);
+        assert_eq!(parse("0"), SteamId::from_xuid(0, false));
+        assert_eq!(SteamId::from_xuid(STEAM_ID64 as i64, true), SteamId::Bot);
+        assert_eq!(SteamId::Bot.to_steam2(), "BOT");
+        assert_eq!(SteamId::Bot.account_id(), None);
+    }
+
+    #[test]
+    fn rejects() {
+        for s in [
+            "",
+            "bot",
+            "STEAM_x:1:5",
+            "STEAM_2:1:5",
+            "STEAM_1:2:5",
+            "STEAM_1:1",
+            "STEAM_1:1:5:0",
+            "STEAM_1:1:2147483648",
+            "[U:1:x]",
+            "[U:1:4294967296]",
+            "-1",
+            "12a",
+            "18446744073709551616",
+        ] {
+            assert!(
+                matches!(s.parse::<SteamId>(), Err(DemoError::BadSteamId(_))),
+                "{s:?} parsed"
+            );
+        }
+    }
+}
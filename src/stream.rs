@@ -0,0 +1,256 @@
+//! A demo front end over any `std::io::Read`.
+//! Only the bytes for the current frame are ever buffered,
+//! so this works on pipes, sockets, and decompressing readers.
+//! When the whole demo is already in memory, prefer [`crate::DemoReader`].
+
+use std::io::Read;
+
+use crate::cursor::Cursor;
+use crate::demo::DemoHeader;
+use crate::error::{DemoError, Result};
+use crate::frame::Frame;
+use crate::packet::COMMAND_INFO_SIZE;
+
+/// The size of the demo header on disk, in bytes.
+const HEADER_SIZE: usize = 8 + 4 + 4 + 260 * 4 + 4 + 4 + 4 + 4;
+/// The command byte, the tick number, and the player slot.
+const FRAME_HEADER_SIZE: usize = 1 + 4 + 1;
+
+/// Parses a demo from a reader, yielding one frame at a time.
+#[derive(Debug)]
+pub struct DemoStream<R: Read> {
+    pub header: DemoHeader<'static>,
+    reader: R,
+    /// The raw bytes of the frame being parsed, reused between frames.
+    buf: Vec<u8>,
+    /// How many bytes into the file the buffer starts.
+    offset: usize,
+    done: bool,
+}
+
+impl<R: Read> DemoStream<R> {
+    pub fn new(mut reader: R) -> Result<DemoStream<R>> {
+        let mut buf = vec![0; HEADER_SIZE];
+        reader.read_exact(&mut buf)?;
+        let header = DemoHeader::parse(&Cursor::new(&buf))?.into_owned();
+        Ok(DemoStream {
+            header,
+            reader,
+            offset: 0,
+            buf,
+            done: false,
+        })
+    }
+
+    /// Append `amt` bytes from the reader to the frame buffer.
+    /// The buffer only grows as the bytes arrive, so a bogus length in a
+    /// short file fails at its end instead of allocating up front.
+    fn fill(&mut self, amt: usize) -> Result<()> {
+        let start = self.buf.len();
+        let got = self
+            .reader
+            .by_ref()
+            .take(amt as u64)
+            .read_to_end(&mut self.buf)?;
+        if got < amt {
+            return Err(DemoError::UnexpectedEof {
+                bit_offset: (self.offset + start + got) * 8,
+                requested: amt * 8,
+                remaining: got * 8,
+            });
+        }
+        Ok(())
+    }
+
+    /// Append a little endian length prefix, and then that many bytes.
+    fn fill_sized(&mut self) -> Result<()> {
+        self.fill(4)?;
+        let start = self.buf.len() - 4;
+        let mut length = [0; 4];
+        length.copy_from_slice(&self.buf[start..]);
+        let length = i32::from_le_bytes(length);
+        if length < 0 {
+            return Err(DemoError::BadLength {
+                what: "chunk",
+                length,
+                byte_offset: self.offset + start,
+            });
+        }
+        self.fill(length as usize)
+    }
+
+    /// Reads the raw bytes of the next frame into the buffer.
+    /// Returns false if the reader was already at its end.
+    fn read_frame_bytes(&mut self) -> Result<bool> {
+        self.offset += self.buf.len();
+        self.buf.clear();
+        self.buf.resize(FRAME_HEADER_SIZE, 0);
+        // A clean EOF is only allowed before the first byte of a frame.
+        let got = self.reader.read(&mut self.buf[..1])?;
+        if got == 0 {
+            return Ok(false);
+        }
+        self.reader.read_exact(&mut self.buf[1..])?;
+
+        // This mirrors the layout that `Command::parse` reads.
+        match self.buf[0] {
+            1 | 2 => {
+                self.fill(COMMAND_INFO_SIZE + 4 + 4)?;
+                self.fill_sized()?;
+            }
+            4 | 6 | 9 => self.fill_sized()?,
+            5 => {
+                self.fill(4)?;
+                self.fill_sized()?;
+            }
+            // No payload, or an unknown command that `Frame::parse` will reject.
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame>> {
+        if !self.read_frame_bytes()? {
+            return Ok(None);
+        }
+        let frame = Frame::parse(&Cursor::with_offset(&self.buf, self.offset))?;
+        Ok(Some(frame))
+    }
+}
+
+impl<R: Read> Iterator for DemoStream<R> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let frame = self.next_frame().transpose();
+        self.done = match &frame {
+            Some(Ok(frame)) => frame.is_last(),
+            Some(Err(_)) | None => true,
+        };
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Command;
+
+    /// Hands out at most `chunk` bytes per read, like a pipe or a socket.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let amt = buf.len().min(self.chunk).min(self.data.len());
+            buf[..amt].copy_from_slice(&self.data[..amt]);
+            self.data = &self.data[amt..];
+            Ok(amt)
+        }
+    }
+
+    fn header() -> Vec<u8> {
+        let mut data = b"HL2DEMO\0".to_vec();
+        data.extend(4i32.to_le_bytes());
+        data.extend(13881u32.to_le_bytes());
+        for name in ["server", "client", "de_test", "csgo"] {
+            let mut field = [0u8; 260];
+            field[..name.len()].copy_from_slice(name.as_bytes());
+            data.extend(field);
+        }
+        data.extend(1.0f32.to_le_bytes());
+        data.extend(64i32.to_le_bytes());
+        data.extend(2i32.to_le_bytes());
+        data.extend(0i32.to_le_bytes());
+        assert_eq!(data.len(), HEADER_SIZE);
+        data
+    }
+
+    fn console_cmd(tick: u32, length: i32, command: &[u8]) -> Vec<u8> {
+        let mut data = vec![4];
+        data.extend(tick.to_le_bytes());
+        data.push(0);
+        data.extend(length.to_le_bytes());
+        data.extend(command);
+        data
+    }
+
+    fn stop(tick: u32) -> Vec<u8> {
+        let mut data = vec![7];
+        data.extend(tick.to_le_bytes());
+        data.push(0);
+        data
+    }
+
+    fn demo(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = header();
+        frames.iter().for_each(|f| data.extend(f));
+        data
+    }
+
+    fn trickle(data: &[u8], chunk: usize) -> DemoStream<Trickle<'_>> {
+        DemoStream::new(Trickle { data, chunk }).unwrap()
+    }
+
+    #[test]
+    fn frames_split_across_reads() {
+        let data = demo(&[console_cmd(1, 4, b"echo"), stop(2)]);
+        for chunk in [1, 3, 7, data.len()] {
+            let stream = trickle(&data, chunk);
+            assert_eq!(stream.header.map_name, "de_test");
+            let frames = stream.collect::<Result<Vec<_>>>().unwrap();
+            assert_eq!(frames.len(), 2);
+            assert!(matches!(&frames[0].command, Command::ConsoleCmd(c) if c == b"echo"));
+            assert_eq!(frames[0].tick_number, 1);
+            assert!(frames[1].is_last());
+        }
+    }
+
+    #[test]
+    fn eof_mid_frame() {
+        let data = demo(&[console_cmd(1, 4, b"echo")]);
+        let mut stream = trickle(&data[..data.len() - 2], 1);
+        let err = stream.next().unwrap().unwrap_err();
+        assert!(matches!(
+            err,
+            DemoError::UnexpectedEof {
+                requested: 32,
+                remaining: 16,
+                ..
+            }
+        ));
+        assert!(stream.next().is_none());
+
+        // In the middle of the frame header, the reader itself runs dry.
+        let data = demo(&[console_cmd(1, 4, b"echo")]);
+        let mut stream = trickle(&data[..HEADER_SIZE + 3], 1);
+        assert!(matches!(stream.next(), Some(Err(DemoError::Io(_)))));
+    }
+
+    #[test]
+    fn bad_length_prefixes() {
+        let data = demo(&[console_cmd(1, -1, b"")]);
+        let err = trickle(&data, 1).next().unwrap().unwrap_err();
+        assert!(matches!(
+            err,
+            DemoError::BadLength {
+                length: -1,
+                byte_offset,
+                ..
+            } if byte_offset == HEADER_SIZE + FRAME_HEADER_SIZE
+        ));
+
+        // Fails at the end of the data, without allocating 2GB first.
+        let data = demo(&[console_cmd(1, 0x7FFF_FFF0, b"echo")]);
+        let err = trickle(&data, 1).next().unwrap().unwrap_err();
+        assert!(matches!(
+            err,
+            DemoError::UnexpectedEof { remaining: 32, .. }
+        ));
+    }
+}
@@ -1,18 +1,42 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 use serde::Serialize;
 
 use crate::cursor::Cursor;
+use crate::error::{DemoError, Result};
+use crate::protos::{CsvcMsgCreateStringTable, CsvcMsgUpdateStringTable};
+use crate::steam_id::SteamId;
+
+/// `SUBSTRING_BITS` in the engine.
+const SUBSTRING_BITS: usize = 5;
+/// `MAX_USERDATA_BITS` in the engine.
+const MAX_USERDATA_BITS: usize = 14;
+/// How many of the most recent strings an entry can copy a prefix from.
+const HISTORY_SIZE: usize = 32;
+/// The bit of `CSVCMsg_CreateStringTable::flags` set when `string_data` is compressed.
+const DATA_COMPRESSED: i32 = 1 << 0;
+/// Starts compressed string table data that is a raw snappy block.
+const SNAPPY_MAGIC: &[u8] = b"SNAP";
+/// Starts compressed string table data that is LZSS compressed.
+const LZSS_MAGIC: &[u8] = b"LZSS";
+
+/// The raw entries of a string table snapshot: each string and its user data, if any.
+pub type RawEntries = Vec<(String, Option<Vec<u8>>)>;
 
 #[derive(Debug, Serialize)]
 pub struct StringTables {
-    tables: Vec<StringTable>,
+    pub tables: Vec<StringTable>,
+    /// Entries that only existed on the recording client, by table name.
+    /// Tables without any are left out.
+    pub client_entries: HashMap<String, RawEntries>,
 }
 
 impl StringTables {
-    pub fn parse(chunk: &Cursor) -> anyhow::Result<StringTables> {
+    pub fn parse(chunk: &Cursor) -> Result<StringTables> {
         let num_tables = chunk.read_u8()?;
         let mut tables = Vec::with_capacity(num_tables as usize);
+        let mut client_entries = HashMap::new();
         for _ in 0..num_tables {
             let table_name = chunk.read_cstr_until()?;
             match table_name.as_ref() {
@@ -25,7 +49,9 @@ impl StringTables {
                         if chunk.read_bit_bool()? {
                             let user_data_size = chunk.read_u16()?;
                             let data = chunk.read_bytes(user_data_size as usize)?.into_owned();
-                            let id = string_name.parse()?;
+                            let id = string_name.parse().map_err(|_| {
+                                DemoError::BadStringTableKey(string_name.to_string())
+                            })?;
                             mapping.insert(id, data);
                         }
                     }
@@ -37,91 +63,654 @@ impl StringTables {
                     for _ in 0..num_strings {
                         let string_name = chunk.read_cstr_until()?.into_owned();
                         if string_name.len() > 100 {
-                            anyhow::bail!("MODEL NAME TOO LONG! {}", string_name.len());
+                            return Err(DemoError::InvalidStringTable(format!(
+                                "Model name too long: {}",
+                                string_name.len()
+                            )));
                         }
                         if chunk.read_bit_bool()? {
                             let user_data_size = chunk.read_u16()?;
                             if user_data_size != 0 {
-                                anyhow::bail!("User data in model precache table.");
+                                return Err(DemoError::InvalidStringTable(
+                                    "User data in model precache table.".to_string(),
+                                ));
                             }
                             models.push(string_name);
                         }
                     }
                     tables.push(StringTable::ModelPrecache(models))
                 }
-                unknown => {
-                    anyhow::bail!("Unknown table name `{unknown}`.")
+                "soundprecache" => tables.push(StringTable::SoundPrecache(read_strings(chunk)?)),
+                "downloadables" => tables.push(StringTable::Downloadables(read_strings(chunk)?)),
+                "decalprecache" => tables.push(StringTable::DecalPrecache(read_strings(chunk)?)),
+                "lightstyles" => {
+                    let mut styles = HashMap::new();
+                    for (string, data) in read_entries(chunk)? {
+                        let style = string
+                            .parse()
+                            .map_err(|_| DemoError::BadStringTableKey(string.clone()))?;
+                        if let Some(data) = data {
+                            let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+                            styles
+                                .insert(style, String::from_utf8_lossy(&data[..end]).into_owned());
+                        }
+                    }
+                    tables.push(StringTable::LightStyles(styles))
                 }
+                other => tables.push(StringTable::Other {
+                    name: other.to_string(),
+                    entries: read_entries(chunk)?,
+                }),
+            }
+            if chunk.read_bit_bool()? {
+                client_entries.insert(table_name.into_owned(), read_entries(chunk)?);
             }
         }
-        Ok(StringTables { tables })
+        Ok(StringTables {
+            tables,
+            client_entries,
+        })
     }
 }
 
+/// Reads the entries of one table, as written by `WriteStringTables` in the engine.
+fn read_entries(chunk: &Cursor) -> Result<RawEntries> {
+    let num_strings = chunk.read_u16()?;
+    let mut entries = Vec::with_capacity(num_strings as usize);
+    for _ in 0..num_strings {
+        let string = chunk.read_cstr_until()?.into_owned();
+        let data = if chunk.read_bit_bool()? {
+            let user_data_size = chunk.read_u16()?;
+            Some(chunk.read_bytes(user_data_size as usize)?.into_owned())
+        } else {
+            None
+        };
+        entries.push((string, data));
+    }
+    Ok(entries)
+}
+
+/// Reads the entries of a table whose user data isn't used.
+fn read_strings(chunk: &Cursor) -> Result<Vec<String>> {
+    Ok(read_entries(chunk)?
+        .into_iter()
+        .map(|(string, _)| string)
+        .collect())
+}
+
 #[derive(Debug, Serialize)]
 pub enum StringTable {
     UserInfo(HashMap<u8, PlayerInfo>),
     InstanceBaseline(HashMap<i32, Vec<u8>>),
     ModelPrecache(Vec<String>),
+    SoundPrecache(Vec<String>),
+    Downloadables(Vec<String>),
+    /// The light style patterns, by style index.
+    LightStyles(HashMap<u8, String>),
+    DecalPrecache(Vec<String>),
+    /// Any table without a typed parser.
+    Other {
+        name: String,
+        entries: RawEntries,
+    },
 }
 
 // TODO: why cant these Strings be &'a str :'(
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct PlayerInfo {
-    version: i64,
-    xuid: i64,
-    name: String,
-    user_id: i32,
-    guid: String,
-    friends_id: i32,
-    friends_name: String,
-    is_fake_player: bool,
-    is_hltv: bool,
-    custom_files: [i32; 4],
-    files_downloaded: u8,
+    pub version: i64,
+    pub xuid: i64,
+    /// The `xuid` as a [`SteamId`], which is [`SteamId::Bot`] for bots and GOTV.
+    pub steam_id: SteamId,
+    pub name: String,
+    pub user_id: i32,
+    pub guid: String,
+    pub friends_id: i32,
+    pub friends_name: String,
+    pub is_fake_player: bool,
+    pub is_hltv: bool,
+    pub custom_files: [i32; 4],
+    pub files_downloaded: u8,
 }
 
 impl PlayerInfo {
-    pub fn parse(chunk: &Cursor) -> anyhow::Result<HashMap<u8, PlayerInfo>> {
+    pub fn parse(chunk: &Cursor) -> Result<HashMap<u8, PlayerInfo>> {
         let mut mapping = HashMap::new();
         let num_strings = chunk.read_u16()?;
         for _ in 0..num_strings {
             let string_name = chunk.read_cstr_until()?;
-            let which_player: u8 = string_name.parse()?;
+            let which_player: u8 = string_name
+                .parse()
+                .map_err(|_| DemoError::BadStringTableKey(string_name.to_string()))?;
             if chunk.read_bit_bool()? {
                 let user_data_size = chunk.read_i16()?;
-                let info_chunk = chunk.chunk_bytes(user_data_size as usize)?;
-                let version = info_chunk.read_i64()?;
-                let xuid = info_chunk.read_i64()?;
-                let name = info_chunk.read_cstr(128)?.into_owned();
-                let user_id = info_chunk.read_i32()?;
-                let guid = info_chunk.read_cstr(33)?.into_owned();
-                let friends_id = info_chunk.read_i32()?;
-                let friends_name = info_chunk.read_cstr(128)?.into_owned();
-                let is_fake_player = info_chunk.read_byte_bool()?;
-                let is_hltv = info_chunk.read_byte_bool()?;
-                let cf1 = info_chunk.read_i32()?;
-                let cf2 = info_chunk.read_i32()?;
-                let cf3 = info_chunk.read_i32()?;
-                let cf4 = info_chunk.read_i32()?;
-                let custom_files = [cf1, cf2, cf3, cf4];
-                let files_downloaded = info_chunk.read_u8()?;
-                let player_info = PlayerInfo {
-                    version,
-                    xuid,
-                    name,
-                    user_id,
-                    guid,
-                    friends_id,
-                    friends_name,
-                    is_fake_player,
-                    is_hltv,
-                    custom_files,
-                    files_downloaded,
-                };
-                mapping.insert(which_player, player_info);
+                let user_data = chunk.read_bytes(user_data_size as usize)?;
+                mapping.insert(which_player, PlayerInfo::from_user_data(&user_data)?);
             }
         }
         Ok(mapping)
     }
+
+    /// Decode the engine's `player_info_t`, the user data of a `userinfo` entry.
+    /// The ints that identify the player are big endian,
+    /// and the padding of the C struct is kept.
+    pub fn from_user_data(data: &[u8]) -> Result<PlayerInfo> {
+        let info = Cursor::new(data);
+        let version = i64::from_be_bytes(read_array(&info)?);
+        let xuid = i64::from_be_bytes(read_array(&info)?);
+        let name = read_fixed_str(&info, 128)?;
+        let user_id = i32::from_be_bytes(read_array(&info)?);
+        let guid = read_fixed_str(&info, 33)?;
+        info.read_bytes(3)?;
+        let friends_id = i32::from_be_bytes(read_array(&info)?);
+        let friends_name = read_fixed_str(&info, 128)?;
+        let is_fake_player = info.read_byte_bool()?;
+        let is_hltv = info.read_byte_bool()?;
+        info.read_bytes(2)?;
+        let cf1 = info.read_i32()?;
+        let cf2 = info.read_i32()?;
+        let cf3 = info.read_i32()?;
+        let cf4 = info.read_i32()?;
+        let custom_files = [cf1, cf2, cf3, cf4];
+        let files_downloaded = info.read_u8()?;
+        Ok(PlayerInfo {
+            version,
+            xuid,
+            steam_id: SteamId::from_xuid(xuid, is_fake_player || is_hltv),
+            name,
+            user_id,
+            guid,
+            friends_id,
+            friends_name,
+            is_fake_player,
+            is_hltv,
+            custom_files,
+            files_downloaded,
+        })
+    }
+}
+
+fn read_array<const N: usize>(data: &Cursor) -> Result<[u8; N]> {
+    let mut array = [0; N];
+    array.copy_from_slice(&data.read_bytes(N)?);
+    Ok(array)
+}
+
+/// Reads a fixed size C string, ignoring anything after the first null byte.
+fn read_fixed_str(data: &Cursor, length: usize) -> Result<String> {
+    let bytes = data.read_bytes(length)?;
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+/// The layout of a networked string table, from `CSVCMsg_CreateStringTable`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StringTableInfo {
+    pub name: String,
+    pub max_entries: i32,
+    pub user_data_fixed_size: bool,
+    pub user_data_size: i32,
+    pub user_data_size_bits: i32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StringTableEntry {
+    pub string: String,
+    pub user_data: Option<Vec<u8>>,
+}
+
+impl From<&CsvcMsgCreateStringTable> for StringTableInfo {
+    fn from(msg: &CsvcMsgCreateStringTable) -> StringTableInfo {
+        StringTableInfo {
+            name: msg.name().to_string(),
+            max_entries: msg.max_entries(),
+            user_data_fixed_size: msg.user_data_fixed_size(),
+            user_data_size: msg.user_data_size(),
+            user_data_size_bits: msg.user_data_size_bits(),
+        }
+    }
+}
+
+impl StringTableInfo {
+    /// Decode `num_entries` changed entries of this table into `entries`.
+    /// This is the bit packed format of `CSVCMsg_CreateStringTable` and
+    /// `CSVCMsg_UpdateStringTable`, see `CNetworkStringTable::ParseUpdate`.
+    /// Returns the index of every entry that changed.
+    pub fn parse_entries(
+        &self,
+        data: &Cursor,
+        num_entries: i32,
+        entries: &mut Vec<StringTableEntry>,
+    ) -> Result<Vec<usize>> {
+        if data.read_bit_bool()? {
+            return Err(DemoError::InvalidStringTable(format!(
+                "Table `{}` is encoded with dictionaries.",
+                self.name
+            )));
+        }
+
+        let mut entry_bits = 0;
+        while self.max_entries >> (entry_bits + 1) > 0 {
+            entry_bits += 1;
+        }
+
+        let mut changed = Vec::with_capacity(num_entries.max(0) as usize);
+        let mut history: Vec<String> = Vec::with_capacity(HISTORY_SIZE);
+        let mut next = 0;
+        for _ in 0..num_entries {
+            let index = if data.read_bit_bool()? {
+                next
+            } else {
+                data.read_ubits(entry_bits)? as usize
+            };
+            next = index + 1;
+            if index >= self.max_entries as usize {
+                return Err(DemoError::InvalidStringTable(format!(
+                    "Entry {index} is out of range for table `{}`.",
+                    self.name
+                )));
+            }
+
+            let string = if data.read_bit_bool()? {
+                if data.read_bit_bool()? {
+                    // Reuse the start of a recent string.
+                    let which = data.read_ubits(5)? as usize;
+                    let length = data.read_ubits(SUBSTRING_BITS)? as usize;
+                    let prefix = history.get(which).ok_or_else(|| {
+                        DemoError::InvalidStringTable(format!(
+                            "Bad history index {which} in table `{}`.",
+                            self.name
+                        ))
+                    })?;
+                    let prefix = &prefix.as_bytes()[..length.min(prefix.len())];
+                    let mut string = String::from_utf8_lossy(prefix).into_owned();
+                    string += &data.read_cstr_until()?;
+                    Some(string)
+                } else {
+                    Some(data.read_cstr_until()?.into_owned())
+                }
+            } else {
+                None
+            };
+
+            let user_data = if data.read_bit_bool()? {
+                Some(self.read_user_data(data)?)
+            } else {
+                None
+            };
+
+            if index >= entries.len() {
+                entries.resize_with(index + 1, StringTableEntry::default);
+                entries[index].string = string.unwrap_or_default();
+            }
+            // Like the engine, an existing entry never changes its string.
+            let entry = &mut entries[index];
+            entry.user_data = user_data;
+            changed.push(index);
+
+            if history.len() == HISTORY_SIZE {
+                history.remove(0);
+            }
+            history.push(entry.string.clone());
+        }
+        Ok(changed)
+    }
+
+    fn read_user_data(&self, data: &Cursor) -> Result<Vec<u8>> {
+        if !self.user_data_fixed_size {
+            let length = data.read_ubits(MAX_USERDATA_BITS)?;
+            return Ok(data.read_bytes(length as usize)?.into_owned());
+        }
+        let bits = self.user_data_size_bits.max(0) as usize;
+        let (whole, rest) = (bits / 8, bits % 8);
+        let mut user_data = data.read_bytes(whole)?.into_owned();
+        if rest > 0 {
+            user_data.push(data.read_ubits(rest)? as u8);
+        }
+        Ok(user_data)
+    }
+}
+
+/// A networked string table, and all of its current entries.
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkedStringTable {
+    pub info: StringTableInfo,
+    pub entries: Vec<StringTableEntry>,
+}
+
+impl NetworkedStringTable {
+    /// Find an entry by its string.
+    pub fn find(&self, string: &str) -> Option<&StringTableEntry> {
+        self.entries.iter().find(|e| e.string == string)
+    }
+}
+
+/// Which entries of which table were changed by a message.
+#[derive(Debug, Clone)]
+pub struct StringTableChange {
+    pub table_id: usize,
+    pub changed: Vec<usize>,
+}
+
+/// Keeps every networked string table up to date, from the
+/// `CSVCMsg_CreateStringTable` and `CSVCMsg_UpdateStringTable` messages.
+/// Tables are identified by the order they were created in.
+#[derive(Debug, Clone, Default)]
+pub struct StringTableManager {
+    tables: Vec<NetworkedStringTable>,
+}
+
+impl StringTableManager {
+    pub fn new() -> StringTableManager {
+        Self::default()
+    }
+
+    pub fn create(&mut self, msg: &CsvcMsgCreateStringTable) -> Result<StringTableChange> {
+        let info = StringTableInfo::from(msg);
+        let data = if msg.flags() & DATA_COMPRESSED != 0 {
+            Cow::Owned(decompress(msg.string_data())?)
+        } else {
+            Cow::Borrowed(msg.string_data())
+        };
+        let mut entries = Vec::with_capacity(msg.num_entries().max(0) as usize);
+        let changed = info.parse_entries(&Cursor::new(&data), msg.num_entries(), &mut entries)?;
+        self.tables.push(NetworkedStringTable { info, entries });
+        Ok(StringTableChange {
+            table_id: self.tables.len() - 1,
+            changed,
+        })
+    }
+
+    pub fn update(&mut self, msg: &CsvcMsgUpdateStringTable) -> Result<StringTableChange> {
+        let table_id = msg.table_id();
+        let table = usize::try_from(table_id)
+            .ok()
+            .and_then(|id| self.tables.get_mut(id))
+            .ok_or(DemoError::UnknownStringTableId(table_id))?;
+        let changed = table.info.parse_entries(
+            &Cursor::new(msg.string_data()),
+            msg.num_changed_entries(),
+            &mut table.entries,
+        )?;
+        Ok(StringTableChange {
+            table_id: table_id as usize,
+            changed,
+        })
+    }
+
+    pub fn by_id(&self, table_id: usize) -> Option<&NetworkedStringTable> {
+        self.tables.get(table_id)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NetworkedStringTable> {
+        self.tables.iter().find(|t| t.info.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &NetworkedStringTable> {
+        self.tables.iter()
+    }
+
+    /// The players in the `userinfo` table, by entity slot (the entry index).
+    pub fn players(&self) -> Result<Vec<(usize, PlayerInfo)>> {
+        let Some(table) = self.get("userinfo") else {
+            return Ok(vec![]);
+        };
+        let mut players = vec![];
+        for (slot, entry) in table.entries.iter().enumerate() {
+            if let Some(data) = &entry.user_data {
+                players.push((slot, PlayerInfo::from_user_data(data)?));
+            }
+        }
+        Ok(players)
+    }
+}
+
+/// Decompress the `string_data` of a `CSVCMsg_CreateStringTable` with the
+/// `DATA_COMPRESSED` flag. That is the uncompressed and compressed sizes,
+/// then the compressed data, which starts with the magic of its algorithm.
+/// Only snappy is supported.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let cursor = Cursor::new(data);
+    let expected = cursor.read_u32()? as usize;
+    let compressed_size = cursor.read_u32()? as usize;
+    let compressed = cursor.read_bytes(compressed_size)?;
+    let Some(block) = compressed.strip_prefix(SNAPPY_MAGIC) else {
+        if compressed.starts_with(LZSS_MAGIC) {
+            return Err(DemoError::LzssCompressed);
+        }
+        let magic = &compressed[..compressed.len().min(4)];
+        return Err(DemoError::InvalidStringTable(format!(
+            "Unknown compression `{}`.",
+            String::from_utf8_lossy(magic)
+        )));
+    };
+    let decompressed = snap::raw::Decoder::new().decompress_vec(block)?;
+    if decompressed.len() != expected {
+        return Err(DemoError::DecompressedSize {
+            expected,
+            found: decompressed.len(),
+        });
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitbuffer::{BitWriteStream, LittleEndian};
+
+    use super::*;
+
+    type Writer<'a> = BitWriteStream<'a, LittleEndian>;
+
+    fn bits(f: impl FnOnce(&mut Writer)) -> Vec<u8> {
+        let mut data = vec![];
+        let mut writer = BitWriteStream::new(&mut data, LittleEndian);
+        f(&mut writer);
+        data
+    }
+
+    fn info(max_entries: i32) -> StringTableInfo {
+        StringTableInfo {
+            name: "test".to_string(),
+            max_entries,
+            user_data_fixed_size: false,
+            user_data_size: 0,
+            user_data_size_bits: 0,
+        }
+    }
+
+    /// An entry at the next index, with a new string and no user data.
+    fn write_entry(w: &mut Writer, string: &str) {
+        w.write_bool(true).unwrap(); // next index
+        w.write_bool(true).unwrap(); // has string
+        w.write_bool(false).unwrap(); // not from history
+        w.write_string(string, None).unwrap();
+        w.write_bool(false).unwrap(); // no user data
+    }
+
+    #[test]
+    fn entry_indices() {
+        // 16 entries take 4 bits to index.
+        let data = bits(|w| {
+            w.write_bool(false).unwrap(); // no dictionary
+            write_entry(w, "a");
+            w.write_bool(false).unwrap();
+            w.write_int(5u32, 4).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_bool(false).unwrap();
+            w.write_string("b", None).unwrap();
+            w.write_bool(false).unwrap();
+            write_entry(w, "c");
+        });
+        let mut entries = vec![];
+        let changed = info(16)
+            .parse_entries(&Cursor::new(&data), 3, &mut entries)
+            .unwrap();
+        assert_eq!(changed, [0, 5, 6]);
+        assert_eq!(entries.len(), 7);
+        assert_eq!(entries[0].string, "a");
+        assert_eq!(entries[5].string, "b");
+        assert_eq!(entries[6].string, "c");
+        assert_eq!(entries[1].string, "");
+    }
+
+    #[test]
+    fn history_keeps_the_last_32_strings() {
+        let data = bits(|w| {
+            w.write_bool(false).unwrap();
+            for i in 0..=HISTORY_SIZE {
+                write_entry(w, &format!("{i:02}-entry"));
+            }
+            // Entry 00 has been pushed out, so the oldest string is 01.
+            w.write_bool(true).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_bool(true).unwrap(); // from history
+            w.write_int(0u32, 5).unwrap();
+            w.write_int(3u32, SUBSTRING_BITS).unwrap();
+            w.write_string("x", None).unwrap();
+            w.write_bool(false).unwrap();
+            // The newest, which is the entry just read.
+            w.write_bool(true).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_int(31u32, 5).unwrap();
+            w.write_int(2u32, SUBSTRING_BITS).unwrap();
+            w.write_string("y", None).unwrap();
+            w.write_bool(false).unwrap();
+        });
+        let mut entries = vec![];
+        let count = HISTORY_SIZE as i32 + 3;
+        info(64)
+            .parse_entries(&Cursor::new(&data), count, &mut entries)
+            .unwrap();
+        assert_eq!(entries[HISTORY_SIZE + 1].string, "01-x");
+        assert_eq!(entries[HISTORY_SIZE + 2].string, "01y");
+    }
+
+    #[test]
+    fn fixed_size_user_data() {
+        let info = StringTableInfo {
+            user_data_fixed_size: true,
+            user_data_size: 2,
+            user_data_size_bits: 10,
+            ..info(2)
+        };
+        let data = bits(|w| {
+            w.write_bool(false).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_bool(false).unwrap();
+            w.write_string("a", None).unwrap();
+            w.write_bool(true).unwrap(); // has user data
+            w.write_int(0xABu32, 8).unwrap();
+            w.write_int(0b11u32, 2).unwrap();
+            write_entry(w, "b");
+        });
+        let mut entries = vec![];
+        info.parse_entries(&Cursor::new(&data), 2, &mut entries)
+            .unwrap();
+        assert_eq!(entries[0].user_data.as_deref(), Some(&[0xAB, 0b11][..]));
+        // Only 10 bits were read, so the next entry still lines up.
+        assert_eq!(entries[1].string, "b");
+        assert_eq!(entries[1].user_data, None);
+    }
+
+    #[test]
+    fn variable_size_user_data() {
+        let data = bits(|w| {
+            w.write_bool(false).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_bool(false).unwrap();
+            w.write_string("a", None).unwrap();
+            w.write_bool(true).unwrap();
+            w.write_int(3u32, MAX_USERDATA_BITS).unwrap();
+            w.write_bytes(&[1, 2, 3]).unwrap();
+        });
+        let mut entries = vec![];
+        info(2)
+            .parse_entries(&Cursor::new(&data), 1, &mut entries)
+            .unwrap();
+        assert_eq!(entries[0].user_data.as_deref(), Some(&[1, 2, 3][..]));
+    }
+
+    fn create_msg(string_data: Vec<u8>, compressed: bool) -> CsvcMsgCreateStringTable {
+        CsvcMsgCreateStringTable {
+            name: Some("test".to_string()),
+            max_entries: Some(16),
+            num_entries: Some(2),
+            string_data: Some(string_data),
+            flags: Some(if compressed { DATA_COMPRESSED } else { 0 }),
+            ..CsvcMsgCreateStringTable::default()
+        }
+    }
+
+    fn two_entries() -> Vec<u8> {
+        bits(|w| {
+            w.write_bool(false).unwrap();
+            write_entry(w, "first");
+            write_entry(w, "second");
+        })
+    }
+
+    /// `data` compressed the way the server does it.
+    fn compress(data: &[u8], magic: &[u8]) -> Vec<u8> {
+        let block = snap::raw::Encoder::new().compress_vec(data).unwrap();
+        let mut compressed = magic.to_vec();
+        compressed.extend(block);
+        let mut out = vec![];
+        out.extend((data.len() as u32).to_le_bytes());
+        out.extend((compressed.len() as u32).to_le_bytes());
+        out.extend(compressed);
+        out
+    }
+
+    fn strings(manager: &StringTableManager) -> Vec<&str> {
+        let table = manager.get("test").unwrap();
+        table.entries.iter().map(|e| e.string.as_str()).collect()
+    }
+
+    #[test]
+    fn create_plain() {
+        let mut manager = StringTableManager::new();
+        let change = manager.create(&create_msg(two_entries(), false)).unwrap();
+        assert_eq!(change.changed, [0, 1]);
+        assert_eq!(strings(&manager), ["first", "second"]);
+    }
+
+    #[test]
+    fn create_compressed() {
+        let data = compress(&two_entries(), SNAPPY_MAGIC);
+        let mut manager = StringTableManager::new();
+        manager.create(&create_msg(data, true)).unwrap();
+        assert_eq!(strings(&manager), ["first", "second"]);
+    }
+
+    #[test]
+    fn decompress_errors() {
+        let mut corrupt = compress(&two_entries(), SNAPPY_MAGIC);
+        corrupt.truncate(corrupt.len() - 2);
+        let len = corrupt.len() as u32 - 8;
+        corrupt[4..8].copy_from_slice(&len.to_le_bytes());
+        assert!(matches!(
+            decompress(&corrupt),
+            Err(DemoError::Decompress(_))
+        ));
+
+        let mut wrong_size = compress(&two_entries(), SNAPPY_MAGIC);
+        wrong_size[0] += 1;
+        assert!(matches!(
+            decompress(&wrong_size),
+            Err(DemoError::DecompressedSize { .. })
+        ));
+
+        let lzss = compress(&two_entries(), LZSS_MAGIC);
+        assert!(matches!(decompress(&lzss), Err(DemoError::LzssCompressed)));
+
+        let truncated = compress(&two_entries(), SNAPPY_MAGIC);
+        assert!(matches!(
+            decompress(&truncated[..truncated.len() - 1]),
+            Err(DemoError::UnexpectedEof { .. })
+        ));
+    }
 }
@@ -0,0 +1,418 @@
+//! Flat tables of what happened in a demo, one row per kill, hit, round, and so on.
+//! They are what the CLI writes out, in every format.
+//!
+//! Players are named by `user_id`, `name` and `steam_id` columns, resolved
+//! through the roster when the row is made. A `user_id` of `None` means there
+//! was no such player, like the attacker in a fall damage death.
+
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::economy::{EconomyRow, EconomyTracker, PlayerEconomyRow};
+use crate::entities::{handle_index, Entity};
+use crate::error::Result;
+use crate::events::{DemoEvent, Event, GrenadeDetonate};
+use crate::frame::Frame;
+use crate::game_state::GameState;
+use crate::kills::{who, Kill};
+use crate::positions::{PlayerPosition, PositionSampler};
+use crate::rounds::{Round, RoundOptions, RoundTracker};
+use crate::scoreboard::{ScoreboardBuilder, ScoreboardRow};
+use crate::steam_id::SteamId;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Tables {
+    pub kills: Vec<Kill>,
+    pub damage: Vec<DamageRow>,
+    pub rounds: Vec<Round>,
+    pub players: Vec<PlayerRow>,
+    pub positions: Vec<PlayerPosition>,
+    pub grenades: Vec<GrenadeRow>,
+    pub purchases: Vec<PurchaseRow>,
+    pub scoreboard: Vec<ScoreboardRow>,
+    pub economy: Vec<EconomyRow>,
+    pub player_economy: Vec<PlayerEconomyRow>,
+}
+
+impl Tables {
+    /// Build the tables from every frame of a demo, sampling positions every `sample_rate` ticks.
+    pub fn from_frames<I>(frames: I, sample_rate: u32, options: RoundOptions) -> Result<Tables>
+    where
+        I: IntoIterator<Item = Result<Frame>>,
+    {
+        let mut builder = TableBuilder::new(sample_rate).round_options(options);
+        for frame in frames {
+            builder.handle_frame(&frame?)?;
+        }
+        Ok(builder.finish())
+    }
+
+    /// Write each table to its own CSV file in `dir`, like `kills.csv`.
+    /// The directory is created if it doesn't exist.
+    /// Every file starts with a header, even for tables without any rows.
+    pub fn write_csv(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        write_csv(&dir.join("kills.csv"), &self.kills)?;
+        write_csv(&dir.join("damage.csv"), &self.damage)?;
+        write_csv(&dir.join("rounds.csv"), &self.rounds)?;
+        write_csv(&dir.join("players.csv"), &self.players)?;
+        write_csv(&dir.join("positions.csv"), &self.positions)?;
+        write_csv(&dir.join("grenades.csv"), &self.grenades)?;
+        write_csv(&dir.join("purchases.csv"), &self.purchases)?;
+        write_csv(&dir.join("scoreboard.csv"), &self.scoreboard)?;
+        write_csv(&dir.join("economy.csv"), &self.economy)?;
+        write_csv(&dir.join("player_economy.csv"), &self.player_economy)?;
+        Ok(())
+    }
+}
+
+fn write_csv<T: Serialize + Default>(path: &Path, rows: &[T]) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    if rows.is_empty() {
+        writer.write_record(&header::<T>()?)?;
+    }
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The header `csv` writes before the first `T`, taken from a row of defaults.
+fn header<T: Serialize + Default>() -> Result<csv::StringRecord> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.serialize(T::default())?;
+    let data = writer.into_inner().map_err(|e| e.into_error())?;
+    Ok(csv::Reader::from_reader(data.as_slice()).headers()?.clone())
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DamageRow {
+    pub tick: u32,
+    pub round: u32,
+    pub victim_user_id: Option<i32>,
+    pub victim_name: String,
+    pub victim_steam_id: Option<SteamId>,
+    pub attacker_user_id: Option<i32>,
+    pub attacker_name: String,
+    pub attacker_steam_id: Option<SteamId>,
+    pub weapon: String,
+    pub dmg_health: i32,
+    pub dmg_armor: i32,
+    /// Health left after the damage.
+    pub health: i32,
+    /// Armor left after the damage.
+    pub armor: i32,
+    pub hitgroup: i32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlayerRow {
+    pub user_id: i32,
+    pub steam_id: Option<SteamId>,
+    pub name: String,
+    pub is_bot: bool,
+    pub is_hltv: bool,
+    /// The player's last team.
+    pub team: i32,
+    pub connected_tick: u32,
+    pub disconnected_tick: Option<u32>,
+}
+
+/// A grenade going off: `hegrenade`, `flashbang`, `smokegrenade` or `inferno`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GrenadeRow {
+    pub tick: u32,
+    pub round: u32,
+    pub grenade: &'static str,
+    pub user_id: Option<i32>,
+    pub name: String,
+    pub steam_id: Option<SteamId>,
+    pub entity_id: i32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PurchaseRow {
+    pub tick: u32,
+    pub round: u32,
+    pub user_id: Option<i32>,
+    pub name: String,
+    pub steam_id: Option<SteamId>,
+    pub team: i32,
+    pub weapon: String,
+}
+
+/// Builds the [`Tables`] one frame at a time.
+#[derive(Debug)]
+pub struct TableBuilder {
+    pub state: GameState,
+    sampler: PositionSampler,
+    rounds: RoundTracker,
+    round_options: RoundOptions,
+    scoreboard: ScoreboardBuilder,
+    economy: EconomyTracker,
+    tables: Tables,
+}
+
+impl TableBuilder {
+    pub fn new(sample_rate: u32) -> TableBuilder {
+        TableBuilder {
+            state: GameState::new(),
+            sampler: PositionSampler::new(sample_rate),
+            rounds: RoundTracker::new(),
+            round_options: RoundOptions::default(),
+            scoreboard: ScoreboardBuilder::new(),
+            economy: EconomyTracker::new(),
+            tables: Tables::default(),
+        }
+    }
+
+    /// Which rounds to leave out. Kills, damage, grenades and purchases
+    /// from those rounds are dropped along with them.
+    pub fn round_options(mut self, options: RoundOptions) -> TableBuilder {
+        self.round_options = options;
+        self
+    }
+
+    pub fn handle_frame(&mut self, frame: &Frame) -> Result<()> {
+        for event in self.state.handle_frame(frame)? {
+            self.handle_event(&event);
+        }
+        self.rounds.update(&self.state, frame.tick_number);
+        self.economy
+            .update(&self.state, self.rounds.rounds().last());
+        let positions = self.sampler.sample(&self.state, frame.tick_number);
+        self.tables.positions.extend(positions);
+        Ok(())
+    }
+
+    /// The finished tables, with a row for every player seen,
+    /// the rounds renumbered once any left out are gone,
+    /// and the scoreboard and economy over the rounds that are left.
+    pub fn finish(mut self) -> Tables {
+        let (rounds, numbers) = self.rounds.finish(&self.state, self.round_options);
+        self.tables.scoreboard = self.scoreboard.finish(&self.state, &rounds, &numbers);
+        (self.tables.economy, self.tables.player_economy) = self.economy.finish(&numbers);
+        let keep = |round: &mut u32| match numbers.get(round) {
+            Some(&number) => {
+                *round = number;
+                true
+            }
+            None => false,
+        };
+        self.tables.kills.retain_mut(|r| keep(&mut r.round));
+        self.tables.damage.retain_mut(|r| keep(&mut r.round));
+        self.tables.grenades.retain_mut(|r| keep(&mut r.round));
+        self.tables.purchases.retain_mut(|r| keep(&mut r.round));
+        self.tables.rounds = rounds;
+        self.tables.players = self
+            .state
+            .roster
+            .iter()
+            .map(|player| PlayerRow {
+                user_id: player.user_id,
+                steam_id: player.steam_id,
+                name: player.name.clone(),
+                is_bot: player.is_bot,
+                is_hltv: player.is_hltv,
+                team: player.team,
+                connected_tick: player.connected_tick,
+                disconnected_tick: player.disconnected_tick,
+            })
+            .collect();
+        self.tables
+    }
+
+    fn handle_event(&mut self, event: &DemoEvent) {
+        self.rounds.handle_event(event, &self.state);
+        let round = self.rounds.current();
+        self.scoreboard.handle_event(event, &self.state, round);
+        self.economy.handle_event(event, &self.state, round);
+        self.add_rows(event, round);
+    }
+
+    fn add_rows(&mut self, event: &DemoEvent, round: u32) {
+        let tick = event.tick;
+        match &event.event {
+            Event::PlayerDeath(death) => {
+                let kill = Kill::new(death, &self.state, tick, round);
+                self.tables.kills.push(kill);
+            }
+            Event::PlayerHurt(hurt) => {
+                let (victim_user_id, victim_name, victim_steam_id) = self.who(hurt.userid);
+                let (attacker_user_id, attacker_name, attacker_steam_id) = self.who(hurt.attacker);
+                self.tables.damage.push(DamageRow {
+                    tick,
+                    round,
+                    victim_user_id,
+                    victim_name,
+                    victim_steam_id,
+                    attacker_user_id,
+                    attacker_name,
+                    attacker_steam_id,
+                    weapon: hurt.weapon.clone(),
+                    dmg_health: hurt.dmg_health,
+                    dmg_armor: hurt.dmg_armor,
+                    health: hurt.health,
+                    armor: hurt.armor,
+                    hitgroup: hurt.hitgroup,
+                });
+            }
+            Event::HegrenadeDetonate(g) => self.grenade(tick, round, "hegrenade", g),
+            Event::FlashbangDetonate(g) => self.grenade(tick, round, "flashbang", g),
+            Event::SmokegrenadeDetonate(g) => self.grenade(tick, round, "smokegrenade", g),
+            Event::InfernoStartburn(burn) => {
+                // The event doesn't say who threw it, but the inferno entity does.
+                let thrower = self
+                    .owner(burn.entityid)
+                    .and_then(|index| self.state.roster.by_entity(index));
+                let (user_id, name, steam_id) = match thrower {
+                    Some(p) => (Some(p.user_id), p.name.clone(), p.steam_id),
+                    None => (None, String::new(), None),
+                };
+                self.tables.grenades.push(GrenadeRow {
+                    tick,
+                    round,
+                    grenade: "inferno",
+                    user_id,
+                    name,
+                    steam_id,
+                    entity_id: burn.entityid,
+                    x: burn.x,
+                    y: burn.y,
+                    z: burn.z,
+                });
+            }
+            Event::ItemPurchase(purchase) => {
+                let (user_id, name, steam_id) = self.who(purchase.userid);
+                self.tables.purchases.push(PurchaseRow {
+                    tick,
+                    round,
+                    user_id,
+                    name,
+                    steam_id,
+                    team: purchase.team,
+                    weapon: purchase.weapon.clone(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn grenade(&mut self, tick: u32, round: u32, grenade: &'static str, g: &GrenadeDetonate) {
+        let (user_id, name, steam_id) = self.who(g.userid);
+        self.tables.grenades.push(GrenadeRow {
+            tick,
+            round,
+            grenade,
+            user_id,
+            name,
+            steam_id,
+            entity_id: g.entityid,
+            x: g.x,
+            y: g.y,
+            z: g.z,
+        });
+    }
+
+    fn who(&self, user_id: i32) -> (Option<i32>, String, Option<SteamId>) {
+        who(&self.state, user_id)
+    }
+
+    /// The entity index of the owner of an entity, from its `m_hOwnerEntity`.
+    fn owner(&self, entity_id: i32) -> Option<usize> {
+        let data_table = self.state.data_table.as_ref()?;
+        let entity: &Entity = self.state.entities.get(usize::try_from(entity_id).ok()?)?;
+        let class = data_table.server_classes.get(entity.class_id)?;
+        handle_index(entity.prop(class, "m_hOwnerEntity")?.as_int()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{PlayerDeath, RoundEnd, RoundStart};
+
+    fn event(tick: u32, event: Event) -> DemoEvent {
+        DemoEvent { tick, event }
+    }
+
+    fn death(tick: u32) -> DemoEvent {
+        event(
+            tick,
+            Event::PlayerDeath(PlayerDeath {
+                userid: 2,
+                attacker: 3,
+                assister: 0,
+                assistedflash: false,
+                weapon: "ak47".to_string(),
+                headshot: false,
+                penetrated: 0,
+                noscope: false,
+                thrusmoke: false,
+                attackerblind: false,
+                distance: 0.0,
+            }),
+        )
+    }
+
+    fn round_end(tick: u32) -> DemoEvent {
+        event(
+            tick,
+            Event::RoundEnd(RoundEnd {
+                winner: 2,
+                reason: 9,
+                message: String::new(),
+                player_count: 10,
+            }),
+        )
+    }
+
+    fn round_start(tick: u32) -> DemoEvent {
+        event(
+            tick,
+            Event::RoundStart(RoundStart {
+                timelimit: 115,
+                fraglimit: 0,
+                objective: String::new(),
+            }),
+        )
+    }
+
+    #[test]
+    fn demo_starting_mid_round() {
+        let mut builder = TableBuilder::new(16);
+        for event in [
+            death(10),
+            round_end(20),
+            event(30, Event::RoundOfficiallyEnded),
+            round_start(40),
+            death(50),
+        ] {
+            builder.handle_event(&event);
+        }
+        let tables = builder.finish();
+        let rounds: Vec<_> = tables.kills.iter().map(|k| k.round).collect();
+        assert_eq!(rounds, [1, 2]);
+        assert_eq!(tables.rounds.len(), 2);
+        assert_eq!(tables.rounds[0].start_tick, None);
+        assert_eq!(tables.rounds[0].end_tick, Some(20));
+        assert_eq!(tables.rounds[1].start_tick, Some(40));
+    }
+
+    #[test]
+    fn empty_tables_have_headers() {
+        let dir = std::env::temp_dir().join(format!("empty-tables-{}", std::process::id()));
+        Tables::default().write_csv(&dir).unwrap();
+        let purchases = fs::read_to_string(dir.join("purchases.csv")).unwrap();
+        assert_eq!(purchases, "tick,round,user_id,name,steam_id,team,weapon\n");
+        let economy = fs::read_to_string(dir.join("economy.csv")).unwrap();
+        assert!(economy.starts_with("round,team,players,"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}